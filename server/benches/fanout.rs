@@ -0,0 +1,51 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use std::sync::Arc;
+use tui_chat_server::configuration::QueueFullPolicy;
+use tui_chat_server::models::chat::{Connection, MessageKind, OutboundQueue, Registry};
+
+const TARGET_CHANNEL: u64 = 1;
+const SUBSCRIBERS: u64 = 10;
+
+/// Builds a registry with `SUBSCRIBERS` connections in the target channel and
+/// `idle` connections spread over other channels.
+fn registry(idle: u64) -> Registry {
+    let mut registry = Registry::default();
+    for id in 0..SUBSCRIBERS + idle {
+        // The queue is closed so sends fail fast instead of queueing up
+        let queue = OutboundQueue::new(1, QueueFullPolicy::DropOldest, registry.dropped_counter());
+        queue.close();
+        let mut connection = Connection::new(id, format!("user{}", id), Arc::new(queue));
+        let channel = match id < SUBSCRIBERS {
+            true => TARGET_CHANNEL,
+            false => 2 + id % 100,
+        };
+        connection.channels.insert(channel);
+        registry.insert(format!("token{}", id), connection);
+    }
+    registry
+}
+
+fn fanout(c: &mut Criterion) {
+    let message = MessageKind::Chat {
+        id: 0,
+        username: "user0".to_string(),
+        msg: "hello".to_string(),
+        message_id: 1,
+        timestamp: 0,
+        parent_id: None,
+        attachment_id: None,
+        expires: None,
+    };
+
+    let mut group = c.benchmark_group("fanout");
+    for idle in [0, 1_000, 10_000] {
+        let registry = registry(idle);
+        group.bench_with_input(BenchmarkId::from_parameter(idle), &idle, |b, _| {
+            b.iter(|| registry.broadcast(TARGET_CHANNEL, &message, "token0"))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, fanout);
+criterion_main!(benches);
@@ -6,6 +6,8 @@ use std::{collections::HashMap, env, net::IpAddr};
 pub struct Settings {
     pub bind: ServerBindSettings,
     pub database: DatabaseSettings,
+    pub chat: ChatSettings,
+    pub attachment: AttachmentSettings,
 }
 
 #[derive(Clone, Deserialize)]
@@ -17,6 +19,94 @@ pub struct DatabaseSettings {
     pub name: String,
 }
 
+#[derive(Clone, Deserialize)]
+pub struct ChatSettings {
+    /// Messages buffered per websocket before `queue_full_policy` applies
+    pub queue_depth: usize,
+    pub queue_full_policy: QueueFullPolicy,
+    /// Seconds between websocket pings
+    pub ping_interval_second: u64,
+    /// Seconds a client has to answer a ping before it is disconnected
+    pub pong_timeout_second: u64,
+    /// Seconds during which a resent message nonce is treated as a duplicate
+    pub dedup_window_second: u64,
+    /// Chat messages a user may send in a burst
+    pub rate_limit_burst: u32,
+    /// Chat messages per second a user's burst allowance refills at
+    pub rate_limit_per_second: f64,
+    /// How terminal escape sequences in chat content are neutralized
+    pub sanitize_policy: SanitizePolicy,
+    /// Longest chat message in bytes
+    pub max_message_bytes: usize,
+    /// Longest chat message in user-perceived characters
+    pub max_message_graphemes: usize,
+    pub max_message_lines: usize,
+    pub allowed_characters: AllowedCharacters,
+    /// Seconds without activity before a connected user shows as idle
+    pub idle_after_second: u64,
+    /// Seconds between checks for scheduled messages to post and messages to expire
+    pub scheduler_interval_second: u64,
+    /// Longest time-to-live a self-destructing message may have
+    pub max_message_ttl_second: u64,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct AttachmentSettings {
+    /// Directory uploaded files are stored in, named by content hash
+    pub directory: String,
+    pub max_size_byte: u64,
+    /// Accepted content types, `type/*` accepts every subtype
+    pub allowed_types: Vec<String>,
+}
+
+impl AttachmentSettings {
+    pub fn is_allowed_type(&self, content_type: &str) -> bool {
+        let essence = content_type.split(';').next().unwrap_or_default().trim();
+        self.allowed_types
+            .iter()
+            .any(|allowed| match allowed.strip_suffix("/*") {
+                Some(prefix) => essence
+                    .split_once('/')
+                    .is_some_and(|(kind, _)| kind.eq_ignore_ascii_case(prefix)),
+                None => essence.eq_ignore_ascii_case(allowed),
+            })
+    }
+}
+
+/// What to do when a websocket client doesn't keep up with its messages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueueFullPolicy {
+    /// Discard the oldest queued message to make room
+    DropOldest,
+    /// Close the connection with a policy violation close frame
+    Disconnect,
+}
+
+/// What to do with terminal control characters and escape sequences in chat
+/// content before it is stored and relayed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SanitizePolicy {
+    /// Remove them
+    Strip,
+    /// Replace them with visible caret notation such as `^[`
+    Escape,
+    /// Keep SGR color and style sequences, remove everything else
+    AllowSgr,
+}
+
+/// Which characters chat messages may contain once sanitized.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AllowedCharacters {
+    Any,
+    /// Reject invisible characters such as zero width spaces and bidi overrides
+    Visible,
+    /// Printable ASCII, newlines and tabs only
+    Ascii,
+}
+
 #[derive(Clone, Deserialize)]
 pub struct ServerBindSettings {
     pub addr: IpAddr,
@@ -97,6 +187,35 @@ pub fn get_configuration() -> Result<Settings, config::ConfigError> {
         .set_default("database.hostname", "127.0.0.1")?
         .set_default("bind.addr", "127.0.0.1")?
         .set_default("bind.port", 8000_u16)?
+        .set_default("chat.queue_depth", 256_u64)?
+        .set_default("chat.queue_full_policy", "drop_oldest")?
+        .set_default("chat.ping_interval_second", 30_u64)?
+        .set_default("chat.pong_timeout_second", 10_u64)?
+        .set_default("chat.dedup_window_second", 300_u64)?
+        .set_default("chat.rate_limit_burst", 5_u64)?
+        .set_default("chat.rate_limit_per_second", 1.0)?
+        .set_default("chat.sanitize_policy", "strip")?
+        .set_default("chat.max_message_bytes", 4000_u64)?
+        .set_default("chat.max_message_graphemes", 2000_u64)?
+        .set_default("chat.max_message_lines", 50_u64)?
+        .set_default("chat.allowed_characters", "visible")?
+        .set_default("chat.idle_after_second", 300_u64)?
+        .set_default("chat.scheduler_interval_second", 1_u64)?
+        .set_default("chat.max_message_ttl_second", 7 * 24 * 60 * 60_u64)?
+        .set_default("attachment.directory", "attachments")?
+        .set_default("attachment.max_size_byte", 10 * 1024 * 1024_u64)?
+        .set_default(
+            "attachment.allowed_types",
+            vec![
+                "text/*",
+                "image/png",
+                "image/jpeg",
+                "image/gif",
+                "application/pdf",
+                "application/gzip",
+                "application/zip",
+            ],
+        )?
         .add_source(config::File::new("config.toml", config::FileFormat::Toml))
         .add_source(CustomEnvironment::with_custom(custom_env))
         .build()?;
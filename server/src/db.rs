@@ -1,8 +1,79 @@
-use mysql::{params, prelude::Queryable, Pool, Row};
+use mysql::{params, prelude::Queryable, Pool, PooledConn, Row, TxOpts, Value};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use crate::configuration::DatabaseSettings;
-use crate::models::chat::ChatTokenInfo;
+use crate::models::chat::{
+    Attachment, ChatMessage, ChatTokenInfo, DmConversation, Member, Mention, MessageKind,
+    ReactionCount, ScheduledMessage, UnreadCount, UserStatus,
+};
+
+/// Number of most recent events kept per channel for replay.
+pub const EVENT_RETENTION: u64 = 1000;
+
+/// Columns selected from `message m JOIN login l` to build a `ChatMessage`.
+const MESSAGE_COLUMNS: &str = concat!(
+    "m.id, m.channel, m.user_id, l.username, m.content, ",
+    "m.created, m.edited, m.parent_id, m.attachment_id, m.expires"
+);
+
+fn message_from_row(row: Row) -> ChatMessage {
+    let (id, channel, user_id, username, msg, timestamp, edited, parent_id, attachment_id, expires) =
+        mysql::from_row(row);
+    ChatMessage {
+        id,
+        channel,
+        user_id,
+        username,
+        msg,
+        timestamp,
+        edited,
+        parent_id,
+        attachment_id,
+        expires,
+        reactions: Vec::new(),
+    }
+}
+
+/// Data type of a column in the current database, `None` if there is no such column.
+fn column_type(conn: &mut PooledConn, table: &str, column: &str) -> Option<String> {
+    conn.exec_first(
+        r"
+        SELECT DATA_TYPE
+        FROM information_schema.COLUMNS
+        WHERE TABLE_SCHEMA = DATABASE()
+          AND TABLE_NAME = :table
+          AND COLUMN_NAME = :column",
+        params! {
+            "table" => table,
+            "column" => column,
+        },
+    )
+    .unwrap()
+}
+
+/// Which side of a message id to page towards when reading channel history.
+pub enum HistoryCursor {
+    Latest,
+    Before(u64),
+    After(u64),
+}
+
+/// What to look for with `Database::search_messages`. Unset filters match
+/// everything.
+pub struct SearchFilter {
+    /// Words to look for, a message matches if it contains any of them
+    pub query: String,
+    pub server_id: Option<u64>,
+    pub channel: Option<u64>,
+    /// Username of the author
+    pub author: Option<String>,
+    /// Earliest creation time, inclusive
+    pub since: Option<u64>,
+    /// Latest creation time, inclusive
+    pub until: Option<u64>,
+    /// Only messages with a smaller id, to page through results
+    pub before: Option<u64>,
+}
 
 #[derive(Clone)]
 pub struct Database {
@@ -53,12 +124,22 @@ impl Database {
         CREATE TABLE IF NOT EXISTS chat_token (
         chat_token VARCHAR(64) PRIMARY KEY,
         expire BIGINT UNSIGNED,
-        channel VARCHAR(64),
+        channel BIGINT UNSIGNED,
         session VARCHAR(64),
         is_used BOOLEAN);",
             (),
         )
         .unwrap();
+        // Older databases stored the channel as text. Chat tokens only live a
+        // few minutes, so dropping them just makes clients request new ones.
+        if column_type(&mut conn, "chat_token", "channel")
+            .is_some_and(|data_type| data_type.eq_ignore_ascii_case("varchar"))
+        {
+            conn.exec::<Row, _, _>("DELETE FROM chat_token", ())
+                .unwrap();
+            conn.exec::<Row, _, _>("ALTER TABLE chat_token MODIFY channel BIGINT UNSIGNED", ())
+                .unwrap();
+        }
         conn.exec::<Vec<_>, &str, ()>(
             "
         CREATE TABLE IF NOT EXISTS server (
@@ -73,7 +154,159 @@ impl Database {
             "
         CREATE TABLE IF NOT EXISTS user_server_relationship (
         server_id BIGINT UNSIGNED NOT NULL,
-        user_id BIGINT UNSIGNED NOT NULL)",
+        user_id BIGINT UNSIGNED NOT NULL,
+        is_admin BOOL NOT NULL DEFAULT FALSE)",
+            (),
+        )
+        .unwrap();
+        if column_type(&mut conn, "user_server_relationship", "is_admin").is_none() {
+            conn.exec::<Row, _, _>(
+                "ALTER TABLE user_server_relationship ADD COLUMN is_admin BOOL NOT NULL DEFAULT FALSE",
+                (),
+            )
+            .unwrap();
+            // Every member could manage a server before admins existed, keep it that way
+            conn.exec::<Row, _, _>("UPDATE user_server_relationship SET is_admin = TRUE", ())
+                .unwrap();
+        }
+        conn.exec::<Vec<_>, &str, ()>(
+            "
+        CREATE TABLE IF NOT EXISTS channel (
+        id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT PRIMARY KEY,
+        server_id BIGINT UNSIGNED,
+        name VARCHAR(32) NOT NULL,
+        last_seq BIGINT UNSIGNED NOT NULL DEFAULT 0,
+        slow_mode_second INT UNSIGNED NOT NULL DEFAULT 0,
+        INDEX (server_id))",
+            (),
+        )
+        .unwrap();
+        conn.exec::<Vec<_>, &str, ()>(
+            "
+        CREATE TABLE IF NOT EXISTS dm_participant (
+        channel_id BIGINT UNSIGNED NOT NULL,
+        user_id BIGINT UNSIGNED NOT NULL,
+        UNIQUE KEY (channel_id, user_id),
+        INDEX (user_id))",
+            (),
+        )
+        .unwrap();
+        conn.exec::<Vec<_>, &str, ()>(
+            "
+        CREATE TABLE IF NOT EXISTS message (
+        id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT PRIMARY KEY,
+        channel BIGINT UNSIGNED NOT NULL,
+        user_id BIGINT UNSIGNED NOT NULL,
+        content TEXT NOT NULL,
+        created BIGINT UNSIGNED NOT NULL,
+        edited BIGINT UNSIGNED,
+        parent_id BIGINT UNSIGNED,
+        nonce VARCHAR(64),
+        attachment_id BIGINT UNSIGNED,
+        expires BIGINT UNSIGNED,
+        INDEX (channel, id),
+        INDEX (parent_id),
+        UNIQUE KEY (user_id, nonce),
+        INDEX (expires),
+        FULLTEXT INDEX (content))",
+            (),
+        )
+        .unwrap();
+        conn.exec::<Vec<_>, &str, ()>(
+            "
+        CREATE TABLE IF NOT EXISTS channel_event (
+        channel BIGINT UNSIGNED NOT NULL,
+        seq BIGINT UNSIGNED NOT NULL,
+        created BIGINT UNSIGNED NOT NULL,
+        message_id BIGINT UNSIGNED,
+        payload TEXT NOT NULL,
+        PRIMARY KEY (channel, seq),
+        INDEX (message_id))",
+            (),
+        )
+        .unwrap();
+        conn.exec::<Vec<_>, &str, ()>(
+            "
+        CREATE TABLE IF NOT EXISTS reaction (
+        message_id BIGINT UNSIGNED NOT NULL,
+        user_id BIGINT UNSIGNED NOT NULL,
+        emoji VARCHAR(32) NOT NULL,
+        UNIQUE KEY (message_id, user_id, emoji))",
+            (),
+        )
+        .unwrap();
+        conn.exec::<Vec<_>, &str, ()>(
+            "
+        CREATE TABLE IF NOT EXISTS mention (
+        message_id BIGINT UNSIGNED NOT NULL,
+        user_id BIGINT UNSIGNED NOT NULL,
+        channel BIGINT UNSIGNED NOT NULL,
+        is_read BOOL NOT NULL DEFAULT FALSE,
+        UNIQUE KEY (message_id, user_id),
+        INDEX (user_id, is_read),
+        INDEX (channel))",
+            (),
+        )
+        .unwrap();
+        conn.exec::<Vec<_>, &str, ()>(
+            "
+        CREATE TABLE IF NOT EXISTS attachment (
+        id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT PRIMARY KEY,
+        channel BIGINT UNSIGNED NOT NULL,
+        user_id BIGINT UNSIGNED NOT NULL,
+        filename VARCHAR(255) NOT NULL,
+        content_type VARCHAR(127) NOT NULL,
+        size BIGINT UNSIGNED NOT NULL,
+        hash CHAR(64) NOT NULL,
+        created BIGINT UNSIGNED NOT NULL,
+        INDEX (channel),
+        INDEX (hash))",
+            (),
+        )
+        .unwrap();
+        conn.exec::<Vec<_>, &str, ()>(
+            "
+        CREATE TABLE IF NOT EXISTS pin (
+        message_id BIGINT UNSIGNED NOT NULL PRIMARY KEY,
+        channel BIGINT UNSIGNED NOT NULL,
+        user_id BIGINT UNSIGNED NOT NULL,
+        created BIGINT UNSIGNED NOT NULL,
+        INDEX (channel))",
+            (),
+        )
+        .unwrap();
+        conn.exec::<Vec<_>, &str, ()>(
+            "
+        CREATE TABLE IF NOT EXISTS scheduled_message (
+        id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT PRIMARY KEY,
+        channel BIGINT UNSIGNED NOT NULL,
+        user_id BIGINT UNSIGNED NOT NULL,
+        content TEXT NOT NULL,
+        attachment_id BIGINT UNSIGNED,
+        send_at BIGINT UNSIGNED NOT NULL,
+        ttl_second BIGINT UNSIGNED,
+        INDEX (send_at),
+        INDEX (user_id))",
+            (),
+        )
+        .unwrap();
+        conn.exec::<Vec<_>, &str, ()>(
+            "
+        CREATE TABLE IF NOT EXISTS user_status (
+        user_id BIGINT UNSIGNED NOT NULL PRIMARY KEY,
+        status VARCHAR(16),
+        text VARCHAR(128))",
+            (),
+        )
+        .unwrap();
+        conn.exec::<Vec<_>, &str, ()>(
+            "
+        CREATE TABLE IF NOT EXISTS read_marker (
+        user_id BIGINT UNSIGNED NOT NULL,
+        channel BIGINT UNSIGNED NOT NULL,
+        message_id BIGINT UNSIGNED NOT NULL,
+        PRIMARY KEY (user_id, channel),
+        INDEX (channel))",
             (),
         )
         .unwrap();
@@ -114,7 +347,7 @@ impl Database {
             return None;
         }
 
-        return Some(id);
+        Some(id)
     }
 
     pub async fn get_username(&self, id: u64) -> Option<String> {
@@ -135,6 +368,22 @@ impl Database {
         Some(username)
     }
 
+    pub async fn get_user_id(&self, username: &str) -> Option<u64> {
+        let mut conn = self.pool.get_conn().unwrap();
+        let result: Vec<Row> = conn
+            .exec(
+                r"SELECT id FROM login WHERE username = :username",
+                params! {"username" => username},
+            )
+            .unwrap();
+
+        if result.is_empty() {
+            return None;
+        }
+
+        Some(mysql::from_row(result[0].clone()))
+    }
+
     pub async fn check_chat_token(&self, chat_token: String) -> Option<ChatTokenInfo> {
         let mut conn = self.pool.get_conn().unwrap();
         let result: Vec<Row> = conn
@@ -147,7 +396,15 @@ impl Database {
                   ON t.session = s.session
                     AND t.chat_token = :chat_token
                 JOIN login l
-                  ON s.id = l.id;",
+                  ON s.id = l.id
+                JOIN channel c
+                  ON t.channel = c.id
+                WHERE EXISTS (
+                    SELECT * FROM user_server_relationship r
+                    WHERE r.server_id = c.server_id AND r.user_id = s.id)
+                  OR EXISTS (
+                    SELECT * FROM dm_participant d
+                    WHERE d.channel_id = c.id AND d.user_id = s.id);",
                 params! {"chat_token" => chat_token.clone()},
             )
             .unwrap();
@@ -164,10 +421,6 @@ impl Database {
             u64,
         ) = mysql::from_row(result[0].clone());
 
-        if is_used {
-            return None;
-        }
-
         let current_time = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
@@ -177,6 +430,18 @@ impl Database {
             return None;
         }
 
+        // The connection using the token may have gone stale without closing,
+        // let its owner take it over
+        if is_used {
+            return Some(ChatTokenInfo {
+                token: chat_token,
+                id,
+                username,
+                channel,
+                in_use: true,
+            });
+        }
+
         if current_time > chat_token_expire {
             conn.exec::<Row, _, _>(
                 r"DELETE FROM chat_token WHERE expire < :current_time",
@@ -197,9 +462,294 @@ impl Database {
             id,
             username,
             channel,
+            in_use: false,
         })
     }
 
+    pub async fn is_server_member(&self, server_id: u64, user_id: u64) -> bool {
+        let mut conn = self.pool.get_conn().unwrap();
+        let result: Vec<Row> = conn
+            .exec(
+                "SELECT server_id FROM user_server_relationship WHERE server_id = :server_id AND user_id = :user_id",
+                params! {
+                    "server_id" => server_id,
+                    "user_id" => user_id,
+                },
+            )
+            .unwrap();
+
+        !result.is_empty()
+    }
+
+    pub async fn is_server_admin(&self, server_id: u64, user_id: u64) -> bool {
+        let mut conn = self.pool.get_conn().unwrap();
+        let result: Vec<Row> = conn
+            .exec(
+                "SELECT server_id FROM user_server_relationship WHERE server_id = :server_id AND user_id = :user_id AND is_admin",
+                params! {
+                    "server_id" => server_id,
+                    "user_id" => user_id,
+                },
+            )
+            .unwrap();
+
+        !result.is_empty()
+    }
+
+    pub async fn set_slow_mode(&self, channel: u64, second: u32) {
+        let mut conn = self.pool.get_conn().unwrap();
+        conn.exec::<Row, _, _>(
+            "UPDATE channel SET slow_mode_second = :second WHERE id = :id",
+            params! {
+                "second" => second,
+                "id" => channel,
+            },
+        )
+        .unwrap();
+    }
+
+    /// Returns how many seconds the user still has to wait before posting to a
+    /// channel in slow mode, or 0 if they may post now.
+    pub async fn slow_mode_wait(&self, channel: u64, user_id: u64) -> u64 {
+        let current_time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        let mut conn = self.pool.get_conn().unwrap();
+        let result: Vec<Row> = conn
+            .exec(
+                r"
+                SELECT
+                  c.slow_mode_second,
+                  (SELECT MAX(m.created) FROM message m
+                   WHERE m.channel = c.id AND m.user_id = :user_id)
+                FROM channel c
+                WHERE c.id = :channel",
+                params! {
+                    "channel" => channel,
+                    "user_id" => user_id,
+                },
+            )
+            .unwrap();
+
+        let (slow_mode_second, last_post): (u64, Option<u64>) = match result.first() {
+            Some(row) => mysql::from_row(row.clone()),
+            None => return 0,
+        };
+        match last_post {
+            Some(last_post) if slow_mode_second > 0 => {
+                (last_post + slow_mode_second).saturating_sub(current_time)
+            }
+            _ => 0,
+        }
+    }
+
+    /// Ids of every user sharing at least one server with `user_id`, including
+    /// the user themselves.
+    pub async fn get_server_peers(&self, user_id: u64) -> Vec<u64> {
+        let mut conn = self.pool.get_conn().unwrap();
+        let result: Vec<Row> = conn
+            .exec(
+                r"
+                SELECT DISTINCT peer.user_id
+                FROM user_server_relationship me
+                JOIN user_server_relationship peer
+                  ON peer.server_id = me.server_id
+                WHERE me.user_id = :user_id",
+                params! {"user_id" => user_id},
+            )
+            .unwrap();
+
+        result.into_iter().map(mysql::from_row).collect()
+    }
+
+    /// Members of a server with their explicit status and status text.
+    pub async fn get_server_member_status(
+        &self,
+        server_id: u64,
+    ) -> Vec<(Member, Option<UserStatus>, Option<String>)> {
+        let mut conn = self.pool.get_conn().unwrap();
+        let result: Vec<Row> = conn
+            .exec(
+                r"
+                SELECT l.id, l.username, s.status, s.text
+                FROM user_server_relationship r
+                JOIN login l
+                  ON r.user_id = l.id
+                LEFT JOIN user_status s
+                  ON s.user_id = l.id
+                WHERE r.server_id = :server_id
+                ORDER BY l.username",
+                params! {"server_id" => server_id},
+            )
+            .unwrap();
+
+        result
+            .into_iter()
+            .map(|row| {
+                let (id, username, status, text): (u64, String, Option<String>, Option<String>) =
+                    mysql::from_row(row);
+                let status = status.as_deref().and_then(UserStatus::parse);
+                (Member { id, username }, status, text)
+            })
+            .collect()
+    }
+
+    pub async fn get_user_status(&self, user_id: u64) -> (Option<UserStatus>, Option<String>) {
+        let mut conn = self.pool.get_conn().unwrap();
+        let result: Vec<Row> = conn
+            .exec(
+                "SELECT status, text FROM user_status WHERE user_id = :user_id",
+                params! {"user_id" => user_id},
+            )
+            .unwrap();
+
+        match result.first() {
+            Some(row) => {
+                let (status, text): (Option<String>, Option<String>) = mysql::from_row(row.clone());
+                (status.as_deref().and_then(UserStatus::parse), text)
+            }
+            None => (None, None),
+        }
+    }
+
+    pub async fn set_user_status(
+        &self,
+        user_id: u64,
+        status: Option<UserStatus>,
+        text: Option<String>,
+    ) {
+        let mut conn = self.pool.get_conn().unwrap();
+        conn.exec::<Row, _, _>(
+            "REPLACE INTO user_status (user_id, status, text) VALUES (:user_id, :status, :text)",
+            params! {
+                "user_id" => user_id,
+                "status" => status.map(|status| status.as_str()),
+                "text" => text,
+            },
+        )
+        .unwrap();
+    }
+
+    pub async fn channel_exists(&self, channel: u64) -> bool {
+        let mut conn = self.pool.get_conn().unwrap();
+        let result: Vec<Row> = conn
+            .exec(
+                "SELECT id FROM channel WHERE id = :id",
+                params! {"id" => channel},
+            )
+            .unwrap();
+
+        !result.is_empty()
+    }
+
+    /// Returns the server a channel belongs to, or `None` if the channel doesn't
+    /// exist or is a direct message conversation.
+    pub async fn get_channel_server(&self, channel: u64) -> Option<u64> {
+        let mut conn = self.pool.get_conn().unwrap();
+        let result: Vec<Row> = conn
+            .exec(
+                "SELECT server_id FROM channel WHERE id = :id AND server_id IS NOT NULL",
+                params! {"id" => channel},
+            )
+            .unwrap();
+
+        if result.is_empty() {
+            return None;
+        }
+
+        Some(mysql::from_row(result[0].clone()))
+    }
+
+    /// Checks that the user is a member of the channel's server, or a participant
+    /// of the conversation if the channel is a direct message.
+    pub async fn can_access_channel(&self, user_id: u64, channel: u64) -> bool {
+        match self.get_channel_server(channel).await {
+            Some(server_id) => self.is_server_member(server_id, user_id).await,
+            None => self.is_dm_participant(channel, user_id).await,
+        }
+    }
+
+    pub async fn is_dm_participant(&self, channel: u64, user_id: u64) -> bool {
+        let mut conn = self.pool.get_conn().unwrap();
+        let result: Vec<Row> = conn
+            .exec(
+                "SELECT channel_id FROM dm_participant WHERE channel_id = :channel AND user_id = :user_id",
+                params! {
+                    "channel" => channel,
+                    "user_id" => user_id,
+                },
+            )
+            .unwrap();
+
+        !result.is_empty()
+    }
+
+    /// Deletes a channel together with everything stored for it.
+    pub async fn delete_channel(&self, channel: u64) {
+        let mut conn = self.pool.get_conn().unwrap();
+        conn.exec::<Row, _, _>(
+            "DELETE FROM channel WHERE id = :channel",
+            params! {"channel" => channel},
+        )
+        .unwrap();
+        conn.exec::<Row, _, _>(
+            r"
+            DELETE r FROM reaction r
+            JOIN message m
+              ON r.message_id = m.id
+            WHERE m.channel = :channel",
+            params! {"channel" => channel},
+        )
+        .unwrap();
+        conn.exec::<Row, _, _>(
+            "DELETE FROM message WHERE channel = :channel",
+            params! {"channel" => channel},
+        )
+        .unwrap();
+        conn.exec::<Row, _, _>(
+            "DELETE FROM chat_token WHERE channel = :channel",
+            params! {"channel" => channel},
+        )
+        .unwrap();
+        conn.exec::<Row, _, _>(
+            "DELETE FROM channel_event WHERE channel = :channel",
+            params! {"channel" => channel},
+        )
+        .unwrap();
+        conn.exec::<Row, _, _>(
+            "DELETE FROM dm_participant WHERE channel_id = :channel",
+            params! {"channel" => channel},
+        )
+        .unwrap();
+        conn.exec::<Row, _, _>(
+            "DELETE FROM mention WHERE channel = :channel",
+            params! {"channel" => channel},
+        )
+        .unwrap();
+        conn.exec::<Row, _, _>(
+            "DELETE FROM read_marker WHERE channel = :channel",
+            params! {"channel" => channel},
+        )
+        .unwrap();
+        conn.exec::<Row, _, _>(
+            "DELETE FROM pin WHERE channel = :channel",
+            params! {"channel" => channel},
+        )
+        .unwrap();
+        conn.exec::<Row, _, _>(
+            "DELETE FROM scheduled_message WHERE channel = :channel",
+            params! {"channel" => channel},
+        )
+        .unwrap();
+        conn.exec::<Row, _, _>(
+            "DELETE FROM attachment WHERE channel = :channel",
+            params! {"channel" => channel},
+        )
+        .unwrap();
+    }
+
     pub async fn delete_session(&self, session: String) {
         let mut conn = self.pool.get_conn().unwrap();
         // delete expired session
@@ -215,4 +765,1124 @@ impl Database {
         )
         .unwrap();
     }
+
+    /// Stores a chat message and returns its id and creation time.
+    /// Stores a message, returns its id and creation time. If the user already
+    /// sent a message with the same `nonce`, nothing is stored and the id and
+    /// creation time of that message are returned as the error.
+    pub async fn insert_message(
+        &self,
+        channel: u64,
+        user_id: u64,
+        content: String,
+        parent_id: Option<u64>,
+        nonce: Option<String>,
+        attachment_id: Option<u64>,
+    ) -> Result<(u64, u64), (u64, u64)> {
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        let mut conn = self.pool.get_conn().unwrap();
+        let res = conn.exec::<Row, _, _>(
+            r"
+            INSERT INTO message (channel, user_id, content, created, parent_id, nonce, attachment_id)
+            VALUES (:channel, :user_id, :content, :created, :parent_id, :nonce, :attachment_id)",
+            params! {
+                "channel" => channel,
+                "user_id" => user_id,
+                "content" => content,
+                "created" => created,
+                "parent_id" => parent_id,
+                "nonce" => nonce.clone(),
+                "attachment_id" => attachment_id,
+            },
+        );
+        match res {
+            Ok(_) => Ok((conn.last_insert_id(), created)),
+            // Duplicate entry, a concurrent retry with the same nonce got there first
+            Err(mysql::Error::MySqlError(e)) if e.code == 1062 => {
+                let existing = conn
+                    .exec_first(
+                        "SELECT id, created FROM message WHERE user_id = :user_id AND nonce = :nonce",
+                        params! {
+                            "user_id" => user_id,
+                            "nonce" => nonce,
+                        },
+                    )
+                    .unwrap()
+                    .unwrap();
+                Err(existing)
+            }
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Makes a message expire at the given time, see `get_expired_messages`.
+    pub async fn set_message_expiry(&self, message_id: u64, expires: u64) {
+        let mut conn = self.pool.get_conn().unwrap();
+        conn.exec::<Row, _, _>(
+            "UPDATE message SET expires = :expires WHERE id = :id",
+            params! {
+                "expires" => expires,
+                "id" => message_id,
+            },
+        )
+        .unwrap();
+    }
+
+    /// Returns `(message_id, channel)` of messages whose time-to-live ran out,
+    /// oldest first.
+    pub async fn get_expired_messages(&self, now: u64, limit: u64) -> Vec<(u64, u64)> {
+        let mut conn = self.pool.get_conn().unwrap();
+        conn.exec(
+            r"
+            SELECT id, channel
+            FROM message
+            WHERE expires <= :now
+            ORDER BY expires
+            LIMIT :limit",
+            params! {
+                "now" => now,
+                "limit" => limit,
+            },
+        )
+        .unwrap()
+    }
+
+    /// Queues a message to be posted at `send_at` and returns its id, `None`
+    /// if the user already has `max_pending` messages waiting to be sent.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn insert_scheduled_message(
+        &self,
+        channel: u64,
+        user_id: u64,
+        content: String,
+        attachment_id: Option<u64>,
+        send_at: u64,
+        ttl_second: Option<u64>,
+        max_pending: u64,
+    ) -> Option<u64> {
+        let mut conn = self.pool.get_conn().unwrap();
+        let mut tx = conn.start_transaction(TxOpts::default()).unwrap();
+        // Locking the user keeps concurrent requests from going over the limit together
+        tx.exec::<Row, _, _>(
+            "SELECT id FROM login WHERE id = :user_id FOR UPDATE",
+            params! {"user_id" => user_id},
+        )
+        .unwrap();
+        let pending: u64 = tx
+            .exec_first(
+                "SELECT COUNT(*) FROM scheduled_message WHERE user_id = :user_id",
+                params! {"user_id" => user_id},
+            )
+            .unwrap()
+            .unwrap();
+        if pending >= max_pending {
+            return None;
+        }
+
+        tx.exec::<Row, _, _>(
+            r"
+            INSERT INTO scheduled_message (channel, user_id, content, attachment_id, send_at, ttl_second)
+            VALUES (:channel, :user_id, :content, :attachment_id, :send_at, :ttl_second)",
+            params! {
+                "channel" => channel,
+                "user_id" => user_id,
+                "content" => content,
+                "attachment_id" => attachment_id,
+                "send_at" => send_at,
+                "ttl_second" => ttl_second,
+            },
+        )
+        .unwrap();
+        let id = tx.last_insert_id().unwrap();
+        tx.commit().unwrap();
+
+        Some(id)
+    }
+
+    /// Moves a pending scheduled message to `send_at`, false if it is gone.
+    pub async fn postpone_scheduled_message(&self, id: u64, send_at: u64) -> bool {
+        let mut conn = self.pool.get_conn().unwrap();
+        conn.exec::<Row, _, _>(
+            "UPDATE scheduled_message SET send_at = :send_at WHERE id = :id",
+            params! {
+                "id" => id,
+                "send_at" => send_at,
+            },
+        )
+        .unwrap();
+
+        conn.affected_rows() > 0
+    }
+
+    /// Pending scheduled messages of a user, next to be sent first.
+    pub async fn get_scheduled_messages(&self, user_id: u64) -> Vec<ScheduledMessage> {
+        self.query_scheduled_messages(
+            "WHERE s.user_id = :user_id ORDER BY s.send_at, s.id",
+            params! {"user_id" => user_id},
+        )
+    }
+
+    /// Scheduled messages due at `now`, oldest first.
+    pub async fn get_due_scheduled_messages(&self, now: u64, limit: u64) -> Vec<ScheduledMessage> {
+        self.query_scheduled_messages(
+            "WHERE s.send_at <= :now ORDER BY s.send_at, s.id LIMIT :limit",
+            params! {
+                "now" => now,
+                "limit" => limit,
+            },
+        )
+    }
+
+    fn query_scheduled_messages(
+        &self,
+        clause: &str,
+        params: mysql::Params,
+    ) -> Vec<ScheduledMessage> {
+        let mut conn = self.pool.get_conn().unwrap();
+        let result: Vec<Row> = conn
+            .exec(
+                format!(
+                    r"
+                    SELECT s.id, s.channel, s.user_id, l.username, s.content, s.attachment_id, s.send_at, s.ttl_second
+                    FROM scheduled_message s
+                    JOIN login l
+                      ON s.user_id = l.id
+                    {}",
+                    clause
+                ),
+                params,
+            )
+            .unwrap();
+
+        result
+            .into_iter()
+            .map(|row| {
+                let (id, channel, user_id, username, msg, attachment_id, send_at, ttl_second) =
+                    mysql::from_row(row);
+                ScheduledMessage {
+                    id,
+                    channel,
+                    user_id,
+                    username,
+                    msg,
+                    attachment_id,
+                    send_at,
+                    ttl_second,
+                }
+            })
+            .collect()
+    }
+
+    /// Removes a pending scheduled message. Without `user_id` any user's
+    /// message is removed. Returns whether there was one.
+    pub async fn delete_scheduled_message(&self, id: u64, user_id: Option<u64>) -> bool {
+        let mut conn = self.pool.get_conn().unwrap();
+        conn.exec::<Row, _, _>(
+            r"
+            DELETE FROM scheduled_message
+            WHERE id = :id
+              AND (:user_id IS NULL OR user_id = :user_id)",
+            params! {
+                "id" => id,
+                "user_id" => user_id,
+            },
+        )
+        .unwrap();
+
+        conn.affected_rows() > 0
+    }
+
+    pub async fn insert_attachment(
+        &self,
+        channel: u64,
+        user_id: u64,
+        filename: &str,
+        content_type: &str,
+        size: u64,
+        hash: &str,
+    ) -> u64 {
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        let mut conn = self.pool.get_conn().unwrap();
+        conn.exec::<Row, _, _>(
+            r"
+            INSERT INTO attachment (channel, user_id, filename, content_type, size, hash, created)
+            VALUES (:channel, :user_id, :filename, :content_type, :size, :hash, :created)",
+            params! {
+                "channel" => channel,
+                "user_id" => user_id,
+                "filename" => filename,
+                "content_type" => content_type,
+                "size" => size,
+                "hash" => hash,
+                "created" => created,
+            },
+        )
+        .unwrap();
+
+        conn.last_insert_id()
+    }
+
+    pub async fn get_attachment(&self, attachment_id: u64) -> Option<Attachment> {
+        let mut conn = self.pool.get_conn().unwrap();
+        let result: Vec<Row> = conn
+            .exec(
+                r"
+                SELECT id, channel, user_id, filename, content_type, size, hash
+                FROM attachment
+                WHERE id = :id",
+                params! {"id" => attachment_id},
+            )
+            .unwrap();
+
+        let (id, channel, user_id, filename, content_type, size, hash) =
+            mysql::from_row(result.first()?.clone());
+        Some(Attachment {
+            id,
+            channel,
+            user_id,
+            filename,
+            content_type,
+            size,
+            hash,
+        })
+    }
+
+    /// Returns the id and timestamp of the message a user sent with `nonce`
+    /// at or after `since`, if any.
+    pub async fn find_message_by_nonce(
+        &self,
+        user_id: u64,
+        nonce: &str,
+        since: u64,
+    ) -> Option<(u64, u64)> {
+        let mut conn = self.pool.get_conn().unwrap();
+        let result: Vec<Row> = conn
+            .exec(
+                r"
+                SELECT id, created FROM message
+                WHERE user_id = :user_id AND nonce = :nonce AND created >= :since
+                ORDER BY id DESC
+                LIMIT 1",
+                params! {
+                    "user_id" => user_id,
+                    "nonce" => nonce,
+                    "since" => since,
+                },
+            )
+            .unwrap();
+
+        result.first().map(|row| mysql::from_row(row.clone()))
+    }
+
+    /// Frees a nonce the user last sent before `before`, so it can be used again.
+    pub async fn release_nonce(&self, user_id: u64, nonce: &str, before: u64) {
+        let mut conn = self.pool.get_conn().unwrap();
+        conn.exec::<Row, _, _>(
+            r"
+            UPDATE message SET nonce = NULL
+            WHERE user_id = :user_id AND nonce = :nonce AND created < :before",
+            params! {
+                "user_id" => user_id,
+                "nonce" => nonce,
+                "before" => before,
+            },
+        )
+        .unwrap();
+    }
+
+    /// Returns the channel and author of a message, or `None` if it doesn't exist.
+    pub async fn get_message_info(&self, message_id: u64) -> Option<(u64, u64)> {
+        let mut conn = self.pool.get_conn().unwrap();
+        let result: Vec<Row> = conn
+            .exec(
+                "SELECT channel, user_id FROM message WHERE id = :id",
+                params! {"id" => message_id},
+            )
+            .unwrap();
+
+        if result.is_empty() {
+            return None;
+        }
+
+        Some(mysql::from_row(result[0].clone()))
+    }
+
+    /// Replaces the content of a message and returns the edit time.
+    pub async fn edit_message(&self, message_id: u64, content: String) -> u64 {
+        let edited = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        let mut conn = self.pool.get_conn().unwrap();
+        conn.exec::<Row, _, _>(
+            "UPDATE message SET content = :content, edited = :edited WHERE id = :id",
+            params! {
+                "content" => content,
+                "edited" => edited,
+                "id" => message_id,
+            },
+        )
+        .unwrap();
+
+        edited
+    }
+
+    pub async fn delete_message(&self, message_id: u64) {
+        let mut conn = self.pool.get_conn().unwrap();
+        conn.exec::<Row, _, _>(
+            "DELETE FROM message WHERE id = :id",
+            params! {"id" => message_id},
+        )
+        .unwrap();
+        conn.exec::<Row, _, _>(
+            "DELETE FROM reaction WHERE message_id = :id",
+            params! {"id" => message_id},
+        )
+        .unwrap();
+        conn.exec::<Row, _, _>(
+            "DELETE FROM mention WHERE message_id = :id",
+            params! {"id" => message_id},
+        )
+        .unwrap();
+        conn.exec::<Row, _, _>(
+            "DELETE FROM pin WHERE message_id = :id",
+            params! {"id" => message_id},
+        )
+        .unwrap();
+        // Logged events still carry the content, keep their place in the log
+        // for replay but only tell that the message is gone
+        let deleted_msg = MessageKind::Deleted { message_id };
+        conn.exec::<Row, _, _>(
+            "UPDATE channel_event SET payload = :payload WHERE message_id = :id",
+            params! {
+                "payload" => serde_json::to_string(&deleted_msg).unwrap(),
+                "id" => message_id,
+            },
+        )
+        .unwrap();
+    }
+
+    /// Whether the user may pin messages in a channel: server admins in server
+    /// channels, every participant in direct messages.
+    pub async fn can_pin(&self, user_id: u64, channel: u64) -> bool {
+        match self.get_channel_server(channel).await {
+            Some(server_id) => self.is_server_admin(server_id, user_id).await,
+            None => self.is_dm_participant(channel, user_id).await,
+        }
+    }
+
+    /// Returns whether the message was newly pinned, or `None` if the channel
+    /// already has `max_pins` pins.
+    pub async fn add_pin(
+        &self,
+        channel: u64,
+        message_id: u64,
+        user_id: u64,
+        max_pins: u64,
+    ) -> Option<bool> {
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        let mut conn = self.pool.get_conn().unwrap();
+        let mut tx = conn.start_transaction(TxOpts::default()).unwrap();
+        // Locking the channel keeps concurrent pins from going over the limit together
+        tx.exec::<Row, _, _>(
+            "SELECT id FROM channel WHERE id = :channel FOR UPDATE",
+            params! {"channel" => channel},
+        )
+        .unwrap();
+
+        // Pinning a pinned message changes nothing, even when the channel is full
+        let pinned: Option<u64> = tx
+            .exec_first(
+                "SELECT message_id FROM pin WHERE message_id = :message_id",
+                params! {"message_id" => message_id},
+            )
+            .unwrap();
+        if pinned.is_some() {
+            return Some(false);
+        }
+        let count: u64 = tx
+            .exec_first(
+                "SELECT COUNT(*) FROM pin WHERE channel = :channel",
+                params! {"channel" => channel},
+            )
+            .unwrap()
+            .unwrap();
+        if count >= max_pins {
+            return None;
+        }
+
+        tx.exec::<Row, _, _>(
+            r"
+            INSERT INTO pin (message_id, channel, user_id, created)
+            VALUES (:message_id, :channel, :user_id, :created)",
+            params! {
+                "message_id" => message_id,
+                "channel" => channel,
+                "user_id" => user_id,
+                "created" => created,
+            },
+        )
+        .unwrap();
+        tx.commit().unwrap();
+
+        Some(true)
+    }
+
+    /// Returns whether the message was pinned.
+    pub async fn remove_pin(&self, message_id: u64) -> bool {
+        let mut conn = self.pool.get_conn().unwrap();
+        conn.exec::<Row, _, _>(
+            "DELETE FROM pin WHERE message_id = :message_id",
+            params! {"message_id" => message_id},
+        )
+        .unwrap();
+
+        conn.affected_rows() > 0
+    }
+
+    /// Pinned messages of a channel, most recently pinned first.
+    pub async fn get_pins(&self, channel: u64) -> Vec<ChatMessage> {
+        let mut conn = self.pool.get_conn().unwrap();
+        let result: Vec<Row> = conn
+            .exec(
+                format!(
+                    r"
+                    SELECT {}
+                    FROM pin p
+                    JOIN message m
+                      ON p.message_id = m.id
+                    JOIN login l
+                      ON m.user_id = l.id
+                    WHERE p.channel = :channel
+                    ORDER BY p.created DESC, m.id DESC",
+                    MESSAGE_COLUMNS
+                ),
+                params! {"channel" => channel},
+            )
+            .unwrap();
+
+        let mut messages: Vec<ChatMessage> = result.into_iter().map(message_from_row).collect();
+        self.attach_reactions(&mut messages).await;
+        messages
+    }
+
+    /// Returns the ids of the users named in `usernames` who can see `channel`.
+    pub async fn resolve_mentions(&self, channel: u64, usernames: &[String]) -> Vec<u64> {
+        if usernames.is_empty() {
+            return Vec::new();
+        }
+
+        let placeholders = vec!["?"; usernames.len()].join(", ");
+        let mut values: Vec<Value> = vec![channel.into()];
+        values.extend(usernames.iter().map(|username| username.as_str().into()));
+
+        let mut conn = self.pool.get_conn().unwrap();
+        let result: Vec<Row> = conn
+            .exec(
+                format!(
+                    r"
+                    SELECT l.id
+                    FROM login l
+                    JOIN channel c
+                      ON c.id = ?
+                    WHERE l.username IN ({})
+                      AND (EXISTS (
+                        SELECT * FROM user_server_relationship r
+                        WHERE r.server_id = c.server_id AND r.user_id = l.id)
+                      OR EXISTS (
+                        SELECT * FROM dm_participant d
+                        WHERE d.channel_id = c.id AND d.user_id = l.id))",
+                    placeholders
+                ),
+                values,
+            )
+            .unwrap();
+
+        result.into_iter().map(mysql::from_row).collect()
+    }
+
+    pub async fn insert_mentions(&self, message_id: u64, channel: u64, user_ids: &[u64]) {
+        let mut conn = self.pool.get_conn().unwrap();
+        conn.exec_batch(
+            r"
+            INSERT IGNORE INTO mention (message_id, user_id, channel)
+            VALUES (:message_id, :user_id, :channel)",
+            user_ids.iter().map(|user_id| {
+                params! {
+                    "message_id" => message_id,
+                    "user_id" => user_id,
+                    "channel" => channel,
+                }
+            }),
+        )
+        .unwrap();
+    }
+
+    /// Unread mentions of a user in channels they can still see, newest first.
+    pub async fn get_unread_mentions(&self, user_id: u64, limit: u64) -> Vec<Mention> {
+        let mut conn = self.pool.get_conn().unwrap();
+        let result: Vec<Row> = conn
+            .exec(
+                r"
+                SELECT m.id, m.channel, c.server_id, m.user_id, l.username, m.content, m.created
+                FROM mention n
+                JOIN message m
+                  ON n.message_id = m.id
+                JOIN login l
+                  ON m.user_id = l.id
+                JOIN channel c
+                  ON m.channel = c.id
+                WHERE n.user_id = :user_id
+                  AND NOT n.is_read
+                  AND (EXISTS (
+                    SELECT * FROM user_server_relationship r
+                    WHERE r.server_id = c.server_id AND r.user_id = n.user_id)
+                  OR EXISTS (
+                    SELECT * FROM dm_participant d
+                    WHERE d.channel_id = c.id AND d.user_id = n.user_id))
+                ORDER BY m.id DESC
+                LIMIT :limit",
+                params! {
+                    "user_id" => user_id,
+                    "limit" => limit,
+                },
+            )
+            .unwrap();
+
+        result
+            .into_iter()
+            .map(|row| {
+                let (message_id, channel, server_id, user_id, username, msg, timestamp) =
+                    mysql::from_row(row);
+                Mention {
+                    message_id,
+                    channel,
+                    server_id,
+                    user_id,
+                    username,
+                    msg,
+                    timestamp,
+                }
+            })
+            .collect()
+    }
+
+    /// Moves the user's read marker in `channel` forward to `message_id` and
+    /// marks the mentions up to it as read. Returns the resulting marker, which
+    /// is never moved backwards.
+    pub async fn set_read_marker(&self, user_id: u64, channel: u64, message_id: u64) -> u64 {
+        let mut conn = self.pool.get_conn().unwrap();
+        conn.exec::<Row, _, _>(
+            r"
+            INSERT INTO read_marker (user_id, channel, message_id)
+            VALUES (:user_id, :channel, :message_id)
+            ON DUPLICATE KEY UPDATE message_id = GREATEST(message_id, VALUES(message_id))",
+            params! {
+                "user_id" => user_id,
+                "channel" => channel,
+                "message_id" => message_id,
+            },
+        )
+        .unwrap();
+        conn.exec::<Row, _, _>(
+            r"
+            UPDATE mention SET is_read = TRUE
+            WHERE user_id = :user_id AND channel = :channel AND message_id <= :message_id",
+            params! {
+                "user_id" => user_id,
+                "channel" => channel,
+                "message_id" => message_id,
+            },
+        )
+        .unwrap();
+
+        let result: Vec<Row> = conn
+            .exec(
+                "SELECT message_id FROM read_marker WHERE user_id = :user_id AND channel = :channel",
+                params! {
+                    "user_id" => user_id,
+                    "channel" => channel,
+                },
+            )
+            .unwrap();
+        mysql::from_row(result[0].clone())
+    }
+
+    /// Unread message and mention counts for every server channel and direct
+    /// message conversation of the user.
+    pub async fn get_unread_counts(&self, user_id: u64) -> Vec<UnreadCount> {
+        let mut conn = self.pool.get_conn().unwrap();
+        let result: Vec<Row> = conn
+            .exec(
+                r"
+                SELECT
+                  c.id, c.server_id, rm.message_id,
+                  (SELECT COUNT(*) FROM message m
+                   WHERE m.channel = c.id
+                     AND m.id > COALESCE(rm.message_id, 0)
+                     AND m.user_id != :user_id),
+                  (SELECT COUNT(*) FROM mention n
+                   WHERE n.channel = c.id AND n.user_id = :user_id AND NOT n.is_read)
+                FROM channel c
+                LEFT JOIN read_marker rm
+                  ON rm.channel = c.id AND rm.user_id = :user_id
+                WHERE c.server_id IN (
+                    SELECT r.server_id FROM user_server_relationship r
+                    WHERE r.user_id = :user_id)
+                  OR c.id IN (
+                    SELECT d.channel_id FROM dm_participant d
+                    WHERE d.user_id = :user_id)
+                ORDER BY c.server_id, c.id",
+                params! {"user_id" => user_id},
+            )
+            .unwrap();
+
+        result
+            .into_iter()
+            .map(|row| {
+                let (channel, server_id, last_read, unread, mentions) = mysql::from_row(row);
+                UnreadCount {
+                    channel,
+                    server_id,
+                    last_read,
+                    unread,
+                    mentions,
+                }
+            })
+            .collect()
+    }
+
+    pub async fn mark_mentions_read(&self, user_id: u64, message_ids: &[u64]) {
+        let mut conn = self.pool.get_conn().unwrap();
+        conn.exec_batch(
+            r"
+            UPDATE mention SET is_read = TRUE
+            WHERE user_id = :user_id AND message_id = :message_id",
+            message_ids.iter().map(|message_id| {
+                params! {
+                    "user_id" => user_id,
+                    "message_id" => message_id,
+                }
+            }),
+        )
+        .unwrap();
+    }
+
+    /// Returns whether the reaction was newly added.
+    pub async fn add_reaction(&self, message_id: u64, user_id: u64, emoji: &str) -> bool {
+        let mut conn = self.pool.get_conn().unwrap();
+        conn.exec::<Row, _, _>(
+            r"
+            INSERT IGNORE INTO reaction (message_id, user_id, emoji)
+            VALUES (:message_id, :user_id, :emoji)",
+            params! {
+                "message_id" => message_id,
+                "user_id" => user_id,
+                "emoji" => emoji,
+            },
+        )
+        .unwrap();
+
+        conn.affected_rows() > 0
+    }
+
+    /// Returns whether a reaction was actually removed.
+    pub async fn remove_reaction(&self, message_id: u64, user_id: u64, emoji: &str) -> bool {
+        let mut conn = self.pool.get_conn().unwrap();
+        conn.exec::<Row, _, _>(
+            r"
+            DELETE FROM reaction
+            WHERE message_id = :message_id AND user_id = :user_id AND emoji = :emoji",
+            params! {
+                "message_id" => message_id,
+                "user_id" => user_id,
+                "emoji" => emoji,
+            },
+        )
+        .unwrap();
+
+        conn.affected_rows() > 0
+    }
+
+    pub async fn count_reaction(&self, message_id: u64, emoji: &str) -> u64 {
+        let mut conn = self.pool.get_conn().unwrap();
+        let result: Vec<Row> = conn
+            .exec(
+                "SELECT COUNT(*) FROM reaction WHERE message_id = :message_id AND emoji = :emoji",
+                params! {
+                    "message_id" => message_id,
+                    "emoji" => emoji,
+                },
+            )
+            .unwrap();
+
+        mysql::from_row(result[0].clone())
+    }
+
+    /// Fills in the aggregated reaction counts of each message.
+    async fn attach_reactions(&self, messages: &mut [ChatMessage]) {
+        if messages.is_empty() {
+            return;
+        }
+
+        let ids: Vec<String> = messages.iter().map(|m| m.id.to_string()).collect();
+        let mut conn = self.pool.get_conn().unwrap();
+        let result: Vec<Row> = conn
+            .query(format!(
+                r"
+                SELECT message_id, emoji, COUNT(*)
+                FROM reaction
+                WHERE message_id IN ({})
+                GROUP BY message_id, emoji
+                ORDER BY MIN(user_id)",
+                ids.join(",")
+            ))
+            .unwrap();
+
+        for row in result {
+            let (message_id, emoji, count): (u64, String, u64) = mysql::from_row(row);
+            if let Some(message) = messages.iter_mut().find(|m| m.id == message_id) {
+                message.reactions.push(ReactionCount { emoji, count });
+            }
+        }
+    }
+
+    /// Reads up to `limit` messages of a channel next to `cursor`, oldest first.
+    /// The second value tells whether more messages exist past the returned page.
+    pub async fn get_history(
+        &self,
+        channel: u64,
+        cursor: HistoryCursor,
+        limit: u64,
+    ) -> (Vec<ChatMessage>, bool) {
+        let (condition, order, cursor_id) = match cursor {
+            HistoryCursor::Latest => ("", "DESC", 0),
+            HistoryCursor::Before(id) => ("AND m.id < :cursor", "DESC", id),
+            HistoryCursor::After(id) => ("AND m.id > :cursor", "ASC", id),
+        };
+
+        let mut conn = self.pool.get_conn().unwrap();
+        let result: Vec<Row> = conn
+            .exec(
+                format!(
+                    r"
+                    SELECT {}
+                    FROM message m
+                    JOIN login l
+                      ON m.user_id = l.id
+                    WHERE m.channel = :channel {}
+                    ORDER BY m.id {}
+                    LIMIT :limit",
+                    MESSAGE_COLUMNS, condition, order
+                ),
+                params! {
+                    "channel" => channel,
+                    "cursor" => cursor_id,
+                    "limit" => limit + 1,
+                },
+            )
+            .unwrap();
+
+        let has_more = result.len() as u64 > limit;
+        let mut messages: Vec<ChatMessage> = result
+            .into_iter()
+            .take(limit as usize)
+            .map(message_from_row)
+            .collect();
+        if order == "DESC" {
+            messages.reverse();
+        }
+        self.attach_reactions(&mut messages).await;
+
+        (messages, has_more)
+    }
+
+    /// Searches the messages of every channel the user can access, newest
+    /// first. Returns at most `limit` messages and whether there are more.
+    pub async fn search_messages(
+        &self,
+        user_id: u64,
+        filter: &SearchFilter,
+        limit: u64,
+    ) -> Result<(Vec<ChatMessage>, bool), mysql::Error> {
+        let mut conditions = String::new();
+        for (value_set, condition) in [
+            (filter.server_id.is_some(), "AND c.server_id = :server_id"),
+            (filter.channel.is_some(), "AND m.channel = :channel"),
+            (filter.author.is_some(), "AND l.username = :author"),
+            (filter.since.is_some(), "AND m.created >= :since"),
+            (filter.until.is_some(), "AND m.created <= :until"),
+            (filter.before.is_some(), "AND m.id < :before"),
+        ] {
+            if value_set {
+                conditions.push_str(condition);
+                conditions.push('\n');
+            }
+        }
+
+        let mut conn = self.pool.get_conn().unwrap();
+        let result: Vec<Row> = conn.exec(
+            format!(
+                r"
+                    SELECT {}
+                    FROM message m
+                    JOIN login l
+                      ON m.user_id = l.id
+                    JOIN channel c
+                      ON m.channel = c.id
+                    WHERE MATCH (m.content) AGAINST (:query IN NATURAL LANGUAGE MODE)
+                      AND (EXISTS (
+                        SELECT * FROM user_server_relationship r
+                        WHERE r.server_id = c.server_id AND r.user_id = :user_id)
+                      OR EXISTS (
+                        SELECT * FROM dm_participant d
+                        WHERE d.channel_id = c.id AND d.user_id = :user_id))
+                    {}
+                    ORDER BY m.id DESC
+                    LIMIT :limit",
+                MESSAGE_COLUMNS, conditions
+            ),
+            params! {
+                "query" => filter.query.as_str(),
+                "user_id" => user_id,
+                "server_id" => filter.server_id,
+                "channel" => filter.channel,
+                "author" => filter.author.as_deref(),
+                "since" => filter.since,
+                "until" => filter.until,
+                "before" => filter.before,
+                "limit" => limit + 1,
+            },
+        )?;
+
+        let has_more = result.len() as u64 > limit;
+        let mut messages: Vec<ChatMessage> = result
+            .into_iter()
+            .take(limit as usize)
+            .map(message_from_row)
+            .collect();
+        self.attach_reactions(&mut messages).await;
+
+        Ok((messages, has_more))
+    }
+
+    pub async fn get_message(&self, message_id: u64) -> Option<ChatMessage> {
+        let mut conn = self.pool.get_conn().unwrap();
+        let result: Vec<Row> = conn
+            .exec(
+                format!(
+                    r"
+                    SELECT {}
+                    FROM message m
+                    JOIN login l
+                      ON m.user_id = l.id
+                    WHERE m.id = :id",
+                    MESSAGE_COLUMNS
+                ),
+                params! {"id" => message_id},
+            )
+            .unwrap();
+
+        let mut messages: Vec<ChatMessage> =
+            result.into_iter().take(1).map(message_from_row).collect();
+        self.attach_reactions(&mut messages).await;
+        messages.pop()
+    }
+
+    /// Reads every reply to a message, oldest first.
+    pub async fn get_replies(&self, parent_id: u64) -> Vec<ChatMessage> {
+        let mut conn = self.pool.get_conn().unwrap();
+        let result: Vec<Row> = conn
+            .exec(
+                format!(
+                    r"
+                    SELECT {}
+                    FROM message m
+                    JOIN login l
+                      ON m.user_id = l.id
+                    WHERE m.parent_id = :parent_id
+                    ORDER BY m.id",
+                    MESSAGE_COLUMNS
+                ),
+                params! {"parent_id" => parent_id},
+            )
+            .unwrap();
+
+        let mut messages: Vec<ChatMessage> = result.into_iter().map(message_from_row).collect();
+        self.attach_reactions(&mut messages).await;
+        messages
+    }
+
+    /// Lists the direct message conversations of a user, most recently active first.
+    pub async fn get_dm_conversations(&self, user_id: u64) -> Vec<DmConversation> {
+        let mut conn = self.pool.get_conn().unwrap();
+        let result: Vec<Row> = conn
+            .exec(
+                r"
+                SELECT
+                  d.channel_id, l.id, l.username,
+                  (SELECT MAX(m.id) FROM message m WHERE m.channel = d.channel_id)
+                FROM dm_participant me
+                JOIN dm_participant d
+                  ON d.channel_id = me.channel_id
+                JOIN login l
+                  ON d.user_id = l.id
+                WHERE me.user_id = :user_id
+                ORDER BY d.channel_id, l.id",
+                params! {"user_id" => user_id},
+            )
+            .unwrap();
+
+        let mut conversations: Vec<DmConversation> = Vec::new();
+        for row in result {
+            let (channel, id, username, last_message_id): (u64, u64, String, Option<u64>) =
+                mysql::from_row(row);
+            let member = Member { id, username };
+            match conversations.last_mut() {
+                Some(conversation) if conversation.id == channel => {
+                    conversation.participants.push(member);
+                }
+                _ => conversations.push(DmConversation {
+                    id: channel,
+                    participants: vec![member],
+                    last_message_id,
+                }),
+            }
+        }
+        conversations.sort_by_key(|conversation| std::cmp::Reverse(conversation.last_message_id));
+
+        conversations
+    }
+
+    /// Creates a direct message conversation between `participants` and returns its channel.
+    pub async fn create_dm(&self, participants: &[u64]) -> u64 {
+        let mut conn = self.pool.get_conn().unwrap();
+        conn.exec::<Row, _, _>("INSERT INTO channel (name) VALUES ('')", ())
+            .unwrap();
+        let channel = conn.last_insert_id();
+
+        conn.exec_batch(
+            "INSERT INTO dm_participant (channel_id, user_id) VALUES (:channel_id, :user_id)",
+            participants.iter().map(|user_id| {
+                params! {
+                    "channel_id" => channel,
+                    "user_id" => user_id,
+                }
+            }),
+        )
+        .unwrap();
+
+        channel
+    }
+
+    /// Appends an event to the channel's log and returns its sequence number,
+    /// or `None` if the channel no longer exists.
+    pub async fn record_event(&self, channel: u64, event: &MessageKind) -> Option<u64> {
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        let mut conn = self.pool.get_conn().unwrap();
+        // LAST_INSERT_ID(expr) hands back the incremented value atomically
+        conn.exec::<Row, _, _>(
+            "UPDATE channel SET last_seq = LAST_INSERT_ID(last_seq + 1) WHERE id = :channel",
+            params! {"channel" => channel},
+        )
+        .unwrap();
+        if conn.affected_rows() == 0 {
+            return None;
+        }
+        let seq = conn.last_insert_id();
+
+        conn.exec::<Row, _, _>(
+            r"
+            INSERT INTO channel_event (channel, seq, created, message_id, payload)
+            VALUES (:channel, :seq, :created, :message_id, :payload)",
+            params! {
+                "channel" => channel,
+                "seq" => seq,
+                "created" => created,
+                "message_id" => event.message_id(),
+                "payload" => serde_json::to_string(event).unwrap(),
+            },
+        )
+        .unwrap();
+
+        // trim the log now and then instead of on every event
+        if seq.is_multiple_of(100) && seq > EVENT_RETENTION {
+            conn.exec::<Row, _, _>(
+                "DELETE FROM channel_event WHERE channel = :channel AND seq <= :seq",
+                params! {
+                    "channel" => channel,
+                    "seq" => seq - EVENT_RETENTION,
+                },
+            )
+            .unwrap();
+        }
+
+        Some(seq)
+    }
+
+    pub async fn get_last_seq(&self, channel: u64) -> u64 {
+        let mut conn = self.pool.get_conn().unwrap();
+        let result: Vec<Row> = conn
+            .exec(
+                "SELECT last_seq FROM channel WHERE id = :channel",
+                params! {"channel" => channel},
+            )
+            .unwrap();
+
+        match result.into_iter().next() {
+            Some(row) => mysql::from_row(row),
+            None => 0,
+        }
+    }
+
+    /// Reads up to `limit` logged events of a channel after `seq`, oldest first.
+    pub async fn get_events_after(
+        &self,
+        channel: u64,
+        seq: u64,
+        limit: u64,
+    ) -> Vec<(u64, MessageKind)> {
+        let mut conn = self.pool.get_conn().unwrap();
+        let result: Vec<Row> = conn
+            .exec(
+                r"
+                SELECT seq, payload FROM channel_event
+                WHERE channel = :channel AND seq > :seq
+                ORDER BY seq
+                LIMIT :limit",
+                params! {
+                    "channel" => channel,
+                    "seq" => seq,
+                    "limit" => limit,
+                },
+            )
+            .unwrap();
+
+        result
+            .into_iter()
+            .filter_map(|row| {
+                let (seq, payload): (u64, String) = mysql::from_row(row);
+                serde_json::from_str(&payload)
+                    .ok()
+                    .map(|event| (seq, event))
+            })
+            .collect()
+    }
 }
@@ -2,5 +2,8 @@ pub mod configuration;
 pub mod db;
 pub mod models;
 pub mod routes;
+pub mod sanitize;
+pub mod scheduler;
 pub mod startup;
 pub mod utils;
+pub mod validate;
@@ -1,8 +1,26 @@
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, sync::Arc};
-use tokio::sync::{mpsc, RwLock};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::{
+    sync::{Notify, RwLock},
+    time::Instant,
+};
+use tokio_util::sync::CancellationToken;
 use warp::ws::Message;
 
+use crate::configuration::QueueFullPolicy;
+
+/// Close code sent when a client is disconnected for not keeping up.
+pub const SLOW_CONSUMER_CLOSE_CODE: u16 = 1008;
+/// How often send rate limits that have fully refilled are forgotten
+const RATE_LIMIT_PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
 #[derive(Debug, Serialize)]
 pub struct ChatTokenResponse {
     pub chat_token: String,
@@ -13,6 +31,152 @@ pub struct ChatTokenInfo {
     pub id: u64,
     pub username: String,
     pub channel: u64,
+    /// The token was already used by a connection that may still be open
+    pub in_use: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReactionCount {
+    pub emoji: String,
+    pub count: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChatMessage {
+    pub id: u64,
+    pub channel: u64,
+    pub user_id: u64,
+    pub username: String,
+    pub msg: String,
+    pub timestamp: u64,
+    pub edited: Option<u64>,
+    pub parent_id: Option<u64>,
+    pub attachment_id: Option<u64>,
+    /// When a self-destructing message will be deleted
+    pub expires: Option<u64>,
+    pub reactions: Vec<ReactionCount>,
+}
+
+/// A message waiting to be posted by the scheduler.
+#[derive(Debug, Serialize)]
+pub struct ScheduledMessage {
+    pub id: u64,
+    pub channel: u64,
+    pub user_id: u64,
+    pub username: String,
+    pub msg: String,
+    pub attachment_id: Option<u64>,
+    pub send_at: u64,
+    /// Time-to-live of the message once posted
+    pub ttl_second: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Attachment {
+    pub id: u64,
+    pub channel: u64,
+    pub user_id: u64,
+    pub filename: String,
+    pub content_type: String,
+    pub size: u64,
+    /// Name of the stored file
+    #[serde(skip)]
+    pub hash: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChatThreadResponse {
+    pub parent: ChatMessage,
+    pub replies: Vec<ChatMessage>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChatHistoryResponse {
+    pub messages: Vec<ChatMessage>,
+    pub has_more: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Member {
+    pub id: u64,
+    pub username: String,
+}
+
+/// Whether a user is connected, derived from their live connections.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PresenceState {
+    Online,
+    /// Connected but inactive for longer than the configured idle time
+    Idle,
+    Offline,
+}
+
+/// A status the user set explicitly.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UserStatus {
+    Away,
+    DoNotDisturb,
+}
+
+impl UserStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UserStatus::Away => "away",
+            UserStatus::DoNotDisturb => "do_not_disturb",
+        }
+    }
+
+    pub fn parse(status: &str) -> Option<Self> {
+        match status {
+            "away" => Some(UserStatus::Away),
+            "do_not_disturb" => Some(UserStatus::DoNotDisturb),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct MemberPresence {
+    pub id: u64,
+    pub username: String,
+    pub state: PresenceState,
+    pub status: Option<UserStatus>,
+    pub text: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UnreadCount {
+    pub channel: u64,
+    /// `None` for direct message conversations
+    pub server_id: Option<u64>,
+    /// Last message the user has read, if any
+    pub last_read: Option<u64>,
+    /// Messages by other users after `last_read`
+    pub unread: u64,
+    pub mentions: u64,
+}
+
+/// A message mentioning the user, as listed by the unread mentions endpoint.
+#[derive(Debug, Serialize)]
+pub struct Mention {
+    pub message_id: u64,
+    pub channel: u64,
+    /// `None` for direct message conversations
+    pub server_id: Option<u64>,
+    pub user_id: u64,
+    pub username: String,
+    pub msg: String,
+    pub timestamp: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DmConversation {
+    /// Channel carrying the conversation's messages
+    pub id: u64,
+    pub participants: Vec<Member>,
+    pub last_message_id: Option<u64>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -22,45 +186,633 @@ pub enum MessageKind {
         id: u64,
         username: String,
         msg: String,
+        message_id: u64,
+        timestamp: u64,
+        /// The message this one replies to, if any.
+        parent_id: Option<u64>,
+        attachment_id: Option<u64>,
+        /// When a self-destructing message will be deleted
+        expires: Option<u64>,
+    },
+    Join {
+        id: u64,
+        username: String,
+    },
+    Leave {
+        id: u64,
+        username: String,
+    },
+    /// Everyone currently connected to the channel, sent to a newcomer after upgrade.
+    Members {
+        members: Vec<Member>,
+    },
+    /// Sent when a user starts or stops typing. A `Chat` from the same user
+    /// implicitly ends their typing state.
+    Typing {
+        id: u64,
+        username: String,
+        typing: bool,
+    },
+    Edited {
+        message_id: u64,
+        msg: String,
+        edited: u64,
+    },
+    Deleted {
+        message_id: u64,
+    },
+    /// User `id` added or removed `emoji`; `count` is the new total for it.
+    Reaction {
+        message_id: u64,
+        id: u64,
+        emoji: String,
+        added: bool,
+        count: u64,
+    },
+    /// Ends a replay of missed events; `seq` is the channel's latest event.
+    /// `complete` is false if some events were too old to replay and the
+    /// client should reload history instead.
+    Resumed {
+        seq: u64,
+        complete: bool,
+    },
+    /// Sent to every connection of a mentioned user, subscribed or not.
+    Mention {
+        message_id: u64,
+        channel: u64,
+        id: u64,
+        username: String,
+        msg: String,
+        timestamp: u64,
+    },
+    /// A message was pinned to or unpinned from the channel by user `id`.
+    Pin {
+        message_id: u64,
+        id: u64,
+        pinned: bool,
+    },
+    /// Sent to everyone sharing a server with a user whose presence changed.
+    Presence {
+        id: u64,
+        username: String,
+        state: PresenceState,
+        status: Option<UserStatus>,
+        text: Option<String>,
     },
+    /// Sent to all connections of a user when their read marker moves.
+    ReadMarker {
+        channel: u64,
+        message_id: u64,
+    },
+    /// Sent back to the sender once a message with a nonce is stored.
+    Ack {
+        nonce: String,
+        message_id: u64,
+        timestamp: u64,
+    },
+    Pong,
+    /// Sent back to a single client whose frame could not be handled.
+    Error {
+        code: String,
+        reason: String,
+    },
+}
+
+impl MessageKind {
+    /// The message an event is about, if any.
+    pub fn message_id(&self) -> Option<u64> {
+        match self {
+            MessageKind::Chat { message_id, .. }
+            | MessageKind::Edited { message_id, .. }
+            | MessageKind::Deleted { message_id }
+            | MessageKind::Reaction { message_id, .. }
+            | MessageKind::Mention { message_id, .. }
+            | MessageKind::Pin { message_id, .. }
+            | MessageKind::ReadMarker { message_id, .. }
+            | MessageKind::Ack { message_id, .. } => Some(*message_id),
+            _ => None,
+        }
+    }
+}
+
+/// Frames a client sends to the server over the websocket.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum ClientMessage {
+    /// Posts to `channel`, or to the focused channel when omitted.
+    Send {
+        msg: String,
+        parent_id: Option<u64>,
+        channel: Option<u64>,
+        /// Client-generated id echoed in the `Ack`, retries with the same
+        /// nonce are not posted twice
+        nonce: Option<String>,
+        /// Attachment uploaded to the same channel, the text may then be empty
+        attachment_id: Option<u64>,
+        /// Deletes the message this many seconds after it is posted
+        ttl_second: Option<u64>,
+    },
+    Edit {
+        message_id: u64,
+        msg: String,
+    },
+    Delete {
+        message_id: u64,
+    },
+    ReactionAdd {
+        message_id: u64,
+        emoji: String,
+    },
+    ReactionRemove {
+        message_id: u64,
+        emoji: String,
+    },
+    /// Marks everything up to and including the message as read.
+    MarkRead {
+        message_id: u64,
+    },
+    TypingStart,
+    TypingStop,
+    /// Subscribes to `channel`, first replaying events after `last_seq` if given.
+    Subscribe {
+        channel: u64,
+        last_seq: Option<u64>,
+    },
+    Unsubscribe {
+        channel: u64,
+    },
+    /// Moves focus to `channel`, leaving the previously focused one.
+    SwitchChannel {
+        channel: u64,
+    },
+    Ping,
+}
+
+/// Bounded queue of messages waiting to be written to one websocket.
+#[derive(Debug)]
+pub struct OutboundQueue {
+    messages: Mutex<VecDeque<Message>>,
+    depth: usize,
+    policy: QueueFullPolicy,
+    available: Notify,
+    closed: CancellationToken,
+    dropped: AtomicU64,
+    dropped_total: Arc<AtomicU64>,
+}
+
+impl OutboundQueue {
+    /// Creates a queue holding up to `depth` messages. Drops are also added to `dropped_total`.
+    pub fn new(depth: usize, policy: QueueFullPolicy, dropped_total: Arc<AtomicU64>) -> Self {
+        Self {
+            messages: Mutex::new(VecDeque::new()),
+            depth: depth.max(1),
+            policy,
+            available: Notify::new(),
+            closed: CancellationToken::new(),
+            dropped: AtomicU64::new(0),
+            dropped_total,
+        }
+    }
+
+    /// Queues a message, applying the full-queue policy. Returns whether it was queued.
+    pub fn push(&self, message: Message) -> bool {
+        if self.closed.is_cancelled() {
+            return false;
+        }
+
+        let mut messages = self.messages.lock().unwrap();
+        if messages.len() >= self.depth {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            self.dropped_total.fetch_add(1, Ordering::Relaxed);
+            match self.policy {
+                QueueFullPolicy::DropOldest => {
+                    messages.pop_front();
+                }
+                QueueFullPolicy::Disconnect => {
+                    messages.clear();
+                    messages.push_back(Message::close_with(
+                        SLOW_CONSUMER_CLOSE_CODE,
+                        "slow consumer",
+                    ));
+                    drop(messages);
+                    self.available.notify_one();
+                    self.close();
+                    return false;
+                }
+            }
+        }
+        messages.push_back(message);
+        drop(messages);
+
+        self.available.notify_one();
+        true
+    }
+
+    /// Waits for the next message. Returns `None` once the queue is closed and drained.
+    pub async fn pop(&self) -> Option<Message> {
+        loop {
+            if let Some(message) = self.try_pop() {
+                return Some(message);
+            }
+            if self.closed.is_cancelled() {
+                return None;
+            }
+            tokio::select! {
+                _ = self.available.notified() => {}
+                _ = self.closed.cancelled() => {}
+            }
+        }
+    }
+
+    /// Takes the next message without waiting.
+    pub fn try_pop(&self) -> Option<Message> {
+        self.messages.lock().unwrap().pop_front()
+    }
+
+    /// Stops accepting messages; anything already queued is still handed out by `pop`.
+    pub fn close(&self) {
+        self.closed.cancel();
+    }
+
+    /// Resolves once the queue has been closed, by policy or by a failed write.
+    pub async fn closed(&self) {
+        self.closed.cancelled().await
+    }
+
+    /// Number of messages this queue has dropped.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// An event as delivered to a client, tagged with the channel it happened in.
+#[derive(Serialize)]
+struct ChannelEvent<'a> {
+    channel: u64,
+    /// Position in the channel's event log, for events that can be replayed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seq: Option<u64>,
+    #[serde(flatten)]
+    event: &'a MessageKind,
 }
 
 #[derive(Debug)]
 pub struct Connection {
     pub id: u64,
     pub username: String,
-    pub current_channel: u64,
-    pub sender: mpsc::UnboundedSender<Message>,
+    /// Channels whose events are delivered to this connection
+    pub channels: HashSet<u64>,
+    pub sender: Arc<OutboundQueue>,
 }
 
 impl Connection {
-    pub fn new(
-        id: u64,
-        username: String,
-        current_channel: u64,
-        sender: mpsc::UnboundedSender<Message>,
-    ) -> Self {
+    pub fn new(id: u64, username: String, sender: Arc<OutboundQueue>) -> Self {
         Self {
             id,
             username,
-            current_channel,
+            channels: HashSet::new(),
             sender,
         }
     }
 
     pub fn send(&self, channel: u64, message: &MessageKind) -> bool {
-        if self.current_channel != channel {
+        self.send_event(channel, None, message)
+    }
+
+    /// Like `send`, tagging the event with its sequence number in the channel.
+    pub fn send_sequenced(&self, channel: u64, seq: u64, message: &MessageKind) -> bool {
+        self.send_event(channel, Some(seq), message)
+    }
+
+    fn send_event(&self, channel: u64, seq: Option<u64>, message: &MessageKind) -> bool {
+        if !self.channels.contains(&channel) {
             return false;
         }
 
+        let event = ChannelEvent {
+            channel,
+            seq,
+            event: message,
+        };
+        self.send_serialized(&event)
+    }
+
+    /// Sends `message` to this connection regardless of its channels.
+    pub fn notify(&self, message: &MessageKind) -> bool {
+        self.send_serialized(message)
+    }
+
+    fn send_serialized(&self, message: &impl Serialize) -> bool {
         let data: String = match serde_json::to_string(message) {
             Ok(data) => data,
             Err(_) => {
                 return false;
             }
         };
-        self.sender.send(Message::text(data)).is_ok()
+        self.sender.push(Message::text(data))
+    }
+}
+
+/// Token bucket limiting how fast a user may send chat messages.
+#[derive(Debug)]
+pub struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(burst: u32, now: Instant) -> Self {
+        TokenBucket {
+            tokens: burst as f64,
+            updated: now,
+        }
+    }
+
+    /// Whether the bucket has refilled to `burst`, so forgetting it changes nothing.
+    pub fn is_full(&self, burst: u32, rate: f64, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens + elapsed * rate >= burst as f64
+    }
+
+    /// Refills at `rate` tokens per second up to `burst`, then takes a token if
+    /// one is left. On failure returns how long until the next token.
+    pub fn try_take(&mut self, burst: u32, rate: f64, now: Instant) -> Result<(), Duration> {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(burst as f64);
+        self.updated = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else if rate > 0.0 {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / rate))
+        } else {
+            Err(Duration::MAX)
+        }
+    }
+}
+
+/// Live websocket connections keyed by chat token, indexed by channel and user
+/// so fan-out only touches the connections that care about an event.
+#[derive(Debug, Default)]
+pub struct Registry {
+    connections: HashMap<String, Connection>,
+    channels: HashMap<u64, HashSet<String>>,
+    users: HashMap<u64, HashSet<String>>,
+    /// Send rate limits, shared by all connections of a user. They outlive the
+    /// connections so reconnecting doesn't grant a fresh burst.
+    rate_limits: HashMap<u64, TokenBucket>,
+    rate_limits_pruned: Option<Instant>,
+    /// Last activity of each connected user and whether they were seen idle
+    activity: HashMap<u64, (Instant, bool)>,
+    dropped_messages: Arc<AtomicU64>,
+    /// Per-channel locks ordering sequenced events, see `sequencer`
+    sequencers: Mutex<HashMap<u64, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+impl Registry {
+    /// Lock to hold while assigning a sequence number in `channel` and
+    /// delivering the event, so subscribers see events in sequence order.
+    pub fn sequencer(&self, channel: u64) -> Arc<tokio::sync::Mutex<()>> {
+        let mut sequencers = self.sequencers.lock().unwrap();
+        // Forget locks nobody is holding or waiting for
+        sequencers.retain(|_, sequencer| Arc::strong_count(sequencer) > 1);
+        sequencers.entry(channel).or_default().clone()
+    }
+
+    /// Counter shared by every connection's queue, for `OutboundQueue::new`.
+    pub fn dropped_counter(&self) -> Arc<AtomicU64> {
+        self.dropped_messages.clone()
+    }
+
+    /// Number of messages dropped across all connections since startup.
+    pub fn dropped_messages(&self) -> u64 {
+        self.dropped_messages.load(Ordering::Relaxed)
+    }
+
+    /// Adds a connection, returns the one it replaces if the token was already connected.
+    pub fn insert(&mut self, token: String, connection: Connection) -> Option<Connection> {
+        let replaced = self.take(&token);
+
+        for channel in connection.channels.iter() {
+            self.channels
+                .entry(*channel)
+                .or_default()
+                .insert(token.clone());
+        }
+        self.users
+            .entry(connection.id)
+            .or_default()
+            .insert(token.clone());
+        self.connections.insert(token, connection);
+        replaced
+    }
+
+    /// Forgets the connection under `token` if it is still the one sending to
+    /// `sender`, and not one that replaced it since.
+    pub fn remove(&mut self, token: &str, sender: &Arc<OutboundQueue>) -> Option<Connection> {
+        match self.connections.get(token) {
+            Some(connection) if Arc::ptr_eq(&connection.sender, sender) => self.take(token),
+            _ => None,
+        }
+    }
+
+    fn take(&mut self, token: &str) -> Option<Connection> {
+        let connection = self.connections.remove(token)?;
+
+        for channel in connection.channels.iter() {
+            remove_index(&mut self.channels, *channel, token);
+        }
+        remove_index(&mut self.users, connection.id, token);
+        if !self.users.contains_key(&connection.id) {
+            self.activity.remove(&connection.id);
+        }
+        Some(connection)
+    }
+
+    pub fn presence(&self, user_id: u64) -> PresenceState {
+        if !self.users.contains_key(&user_id) {
+            return PresenceState::Offline;
+        }
+        match self.activity.get(&user_id) {
+            Some((_, true)) => PresenceState::Idle,
+            _ => PresenceState::Online,
+        }
+    }
+
+    /// Records activity of a connected user. Returns whether they were idle.
+    pub fn touch(&mut self, user_id: u64, now: Instant) -> bool {
+        if !self.users.contains_key(&user_id) {
+            return false;
+        }
+        match self.activity.insert(user_id, (now, false)) {
+            Some((_, idle)) => idle,
+            None => false,
+        }
+    }
+
+    /// Marks a user idle if none of their connections were active within
+    /// `idle_after`. Returns whether they just became idle.
+    pub fn mark_idle(&mut self, user_id: u64, now: Instant, idle_after: Duration) -> bool {
+        match self.activity.get_mut(&user_id) {
+            Some((last_active, idle)) if !*idle && now >= *last_active + idle_after => {
+                *idle = true;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Takes a send token from the user's bucket, see `TokenBucket::try_take`.
+    pub fn take_send_token(
+        &mut self,
+        user_id: u64,
+        burst: u32,
+        rate: f64,
+        now: Instant,
+    ) -> Result<(), Duration> {
+        let prune_due = self
+            .rate_limits_pruned
+            .is_none_or(|pruned| now >= pruned + RATE_LIMIT_PRUNE_INTERVAL);
+        if prune_due {
+            self.rate_limits
+                .retain(|_, bucket| !bucket.is_full(burst, rate, now));
+            self.rate_limits_pruned = Some(now);
+        }
+
+        self.rate_limits
+            .entry(user_id)
+            .or_insert_with(|| TokenBucket::new(burst, now))
+            .try_take(burst, rate, now)
+    }
+
+    pub fn get(&self, token: &str) -> Option<&Connection> {
+        self.connections.get(token)
+    }
+
+    pub fn len(&self) -> usize {
+        self.connections.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.connections.is_empty()
+    }
+
+    /// Returns whether the connection was newly subscribed to `channel`.
+    pub fn subscribe(&mut self, token: &str, channel: u64) -> bool {
+        let connection = match self.connections.get_mut(token) {
+            Some(connection) => connection,
+            None => {
+                return false;
+            }
+        };
+        if !connection.channels.insert(channel) {
+            return false;
+        }
+
+        self.channels
+            .entry(channel)
+            .or_default()
+            .insert(token.to_string());
+        true
+    }
+
+    /// Returns whether the connection was subscribed to `channel` before.
+    pub fn unsubscribe(&mut self, token: &str, channel: u64) -> bool {
+        let connection = match self.connections.get_mut(token) {
+            Some(connection) => connection,
+            None => {
+                return false;
+            }
+        };
+        if !connection.channels.remove(&channel) {
+            return false;
+        }
+
+        remove_index(&mut self.channels, channel, token);
+        true
+    }
+
+    /// Connections subscribed to `channel`.
+    pub fn channel_connections(
+        &self,
+        channel: u64,
+    ) -> impl Iterator<Item = (&String, &Connection)> {
+        self.lookup(self.channels.get(&channel))
+    }
+
+    /// Every live connection of a user.
+    pub fn user_connections(&self, user_id: u64) -> impl Iterator<Item = (&String, &Connection)> {
+        self.lookup(self.users.get(&user_id))
+    }
+
+    fn lookup<'a>(
+        &'a self,
+        tokens: Option<&'a HashSet<String>>,
+    ) -> impl Iterator<Item = (&'a String, &'a Connection)> {
+        tokens
+            .into_iter()
+            .flatten()
+            .filter_map(|token| self.connections.get_key_value(token))
+    }
+
+    /// Lists the distinct users with a live connection to `channel`.
+    pub fn channel_members(&self, channel: u64) -> Vec<Member> {
+        let mut members: Vec<Member> = Vec::new();
+        for (_, connection) in self.channel_connections(channel) {
+            if members.iter().any(|member| member.id == connection.id) {
+                continue;
+            }
+            members.push(Member {
+                id: connection.id,
+                username: connection.username.clone(),
+            });
+        }
+        members
+    }
+
+    /// Whether the user is in `channel` through a connection other than `except_token`.
+    pub fn is_user_in_channel(&self, user_id: u64, channel: u64, except_token: &str) -> bool {
+        self.user_connections(user_id).any(|(token, connection)| {
+            token != except_token && connection.channels.contains(&channel)
+        })
+    }
+
+    /// Sends `message` to every connection in `channel` except the one holding `except_token`.
+    pub fn broadcast(&self, channel: u64, message: &MessageKind, except_token: &str) {
+        for (token, connection) in self.channel_connections(channel) {
+            if token == except_token {
+                continue;
+            }
+            connection.send(channel, message);
+        }
+    }
+
+    /// Like `broadcast`, for an event recorded in the channel's log under `seq`.
+    pub fn publish(&self, channel: u64, seq: u64, message: &MessageKind, except_token: &str) {
+        for (token, connection) in self.channel_connections(channel) {
+            if token == except_token {
+                continue;
+            }
+            connection.send_sequenced(channel, seq, message);
+        }
+    }
+
+    /// Sends `message` to every connection of a user regardless of their channels.
+    pub fn notify_user(&self, user_id: u64, message: &MessageKind) {
+        for (_, connection) in self.user_connections(user_id) {
+            connection.notify(message);
+        }
+    }
+}
+
+fn remove_index(index: &mut HashMap<u64, HashSet<String>>, key: u64, token: &str) {
+    if let Some(tokens) = index.get_mut(&key) {
+        tokens.remove(token);
+        if tokens.is_empty() {
+            index.remove(&key);
+        }
     }
 }
 
-pub type Connections = Arc<RwLock<HashMap<String, Connection>>>; // id, User
+pub type Connections = Arc<RwLock<Registry>>;
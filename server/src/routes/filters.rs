@@ -1,6 +1,6 @@
-use crate::configuration::Settings;
-use crate::db::Database;
-use crate::models::chat::Connections;
+use crate::configuration::{AttachmentSettings, ChatSettings, Settings};
+use crate::db::{Database, HistoryCursor, SearchFilter};
+use crate::models::chat::{Connections, UserStatus};
 use crate::routes::handlers;
 
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
@@ -47,6 +47,11 @@ pub struct ServerInviteData {
     pub id: u64,
 }
 
+#[derive(Clone, Deserialize)]
+pub struct ServerPresenceData {
+    pub id: u64,
+}
+
 #[derive(Clone, Deserialize)]
 pub struct ServerCreateData {
     pub name: String,
@@ -65,6 +70,101 @@ pub struct ServerModifyData {
     pub public: bool,
 }
 
+#[derive(Clone, Deserialize)]
+pub struct ChannelCreateData {
+    pub server_id: u64,
+    pub name: String,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct ChannelListData {
+    pub server_id: u64,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct ChannelRenameData {
+    pub id: u64,
+    pub name: String,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct ChannelDeleteData {
+    pub id: u64,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct ChannelSlowModeData {
+    pub id: u64,
+    pub second: u32,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct MessageEditData {
+    pub id: u64,
+    pub msg: String,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct MessageDeleteData {
+    pub id: u64,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct PinData {
+    pub id: u64,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct ReactionData {
+    pub id: u64,
+    pub emoji: String,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct DmOpenData {
+    pub usernames: Vec<String>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct StatusData {
+    /// Explicit status, `None` clears it
+    pub status: Option<UserStatus>,
+    pub text: Option<String>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct ReadMarkerData {
+    /// Last message read
+    pub id: u64,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct ScheduleData {
+    pub channel: u64,
+    pub msg: String,
+    /// Unix time to post the message at
+    pub send_at: u64,
+    pub attachment_id: Option<u64>,
+    /// Deletes the message this many seconds after it is posted
+    pub ttl_second: Option<u64>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ScheduleResponse {
+    pub id: u64,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct ScheduleCancelData {
+    pub id: u64,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct MentionReadData {
+    /// Ids of the messages whose mentions are marked read
+    pub ids: Vec<u64>,
+}
+
 #[derive(Clone, Debug, Serialize)]
 pub struct InvalidParamsDetail {
     pub name: String,
@@ -108,6 +208,8 @@ pub enum ApiError {
     NotAuthorized,
     NotProcessable(Vec<InvalidParamsDetail>),
     InvalidQuery,
+    /// A file could not be stored, the disk may be full
+    StorageError,
 }
 
 impl warp::reject::Reject for ApiError {}
@@ -115,6 +217,8 @@ impl warp::reject::Reject for ApiError {}
 pub struct Api {
     pub database: Database,
     pub ws_connections: Connections,
+    pub chat_settings: ChatSettings,
+    pub attachment_settings: AttachmentSettings,
 }
 
 impl Api {
@@ -124,6 +228,8 @@ impl Api {
         Self {
             database,
             ws_connections: connections,
+            chat_settings: settings.chat,
+            attachment_settings: settings.attachment,
         }
     }
 
@@ -205,16 +311,269 @@ impl Api {
                 },
             );
 
+        let history = warp::path!("history")
+            .and(warp::get())
+            .and(self.ensure_authentication().await)
+            .and(self.with_db())
+            .and(warp::query::<HashMap<String, String>>())
+            .and_then(
+                |auth: AuthDetail, database: Database, query: HashMap<String, String>| async move {
+                    let channel = match query_u64(&query, "channel")? {
+                        Some(channel) => channel,
+                        None => {
+                            return Err(warp::reject::custom(ApiError::InvalidQuery));
+                        }
+                    };
+                    let cursor = match (query_u64(&query, "before")?, query_u64(&query, "after")?) {
+                        (None, None) => HistoryCursor::Latest,
+                        (Some(before), None) => HistoryCursor::Before(before),
+                        (None, Some(after)) => HistoryCursor::After(after),
+                        (Some(_), Some(_)) => {
+                            return Err(warp::reject::custom(ApiError::InvalidQuery));
+                        }
+                    };
+                    let limit = query_u64(&query, "limit")?;
+                    handlers::chat::history(auth, database, channel, cursor, limit).await
+                },
+            );
+
+        let search = warp::path!("search")
+            .and(warp::get())
+            .and(self.ensure_authentication().await)
+            .and(self.with_db())
+            .and(warp::query::<HashMap<String, String>>())
+            .and_then(
+                |auth: AuthDetail, database: Database, query: HashMap<String, String>| async move {
+                    let filter = SearchFilter {
+                        query: match query.get("query") {
+                            Some(text) => text.clone(),
+                            None => {
+                                return Err(warp::reject::custom(ApiError::InvalidQuery));
+                            }
+                        },
+                        server_id: query_u64(&query, "server")?,
+                        channel: query_u64(&query, "channel")?,
+                        author: query.get("author").cloned(),
+                        since: query_u64(&query, "since")?,
+                        until: query_u64(&query, "until")?,
+                        before: query_u64(&query, "before")?,
+                    };
+                    let limit = query_u64(&query, "limit")?;
+                    handlers::chat::search(auth, database, filter, limit).await
+                },
+            );
+
+        // Leave room for the multipart boundaries and headers
+        let max_upload_length = self.attachment_settings.max_size_byte + 64 * 1024;
+        let upload = warp::path!("attachment" / "upload")
+            .and(warp::post())
+            .and(self.ensure_authentication().await)
+            .and(self.with_db())
+            .and(self.with_attachment_settings())
+            .and(warp::query::<HashMap<String, String>>())
+            .and(warp::multipart::form().max_length(max_upload_length))
+            .and_then(
+                |auth: AuthDetail,
+                 database: Database,
+                 settings: AttachmentSettings,
+                 query: HashMap<String, String>,
+                 form: warp::multipart::FormData| async move {
+                    let channel = match query_u64(&query, "channel")? {
+                        Some(channel) => channel,
+                        None => {
+                            return Err(warp::reject::custom(ApiError::InvalidQuery));
+                        }
+                    };
+                    handlers::attachment::upload(auth, database, settings, channel, form).await
+                },
+            );
+
+        let download = warp::path!("attachment")
+            .and(warp::get())
+            .and(self.ensure_authentication().await)
+            .and(self.with_db())
+            .and(self.with_attachment_settings())
+            .and(warp::query::<HashMap<String, String>>())
+            .and_then(
+                |auth: AuthDetail,
+                 database: Database,
+                 settings: AttachmentSettings,
+                 query: HashMap<String, String>| async move {
+                    let attachment_id = match query_u64(&query, "id")? {
+                        Some(attachment_id) => attachment_id,
+                        None => {
+                            return Err(warp::reject::custom(ApiError::InvalidQuery));
+                        }
+                    };
+                    handlers::attachment::download(auth, database, settings, attachment_id).await
+                },
+            );
+
+        let pin = warp::path!("pin")
+            .and(warp::post())
+            .and(self.ensure_authentication().await)
+            .and(json_body::<PinData>())
+            .and(self.with_db())
+            .and(self.with_ws_connections())
+            .and_then(handlers::chat::pin);
+
+        let unpin = warp::path!("unpin")
+            .and(warp::post())
+            .and(self.ensure_authentication().await)
+            .and(json_body::<PinData>())
+            .and(self.with_db())
+            .and(self.with_ws_connections())
+            .and_then(handlers::chat::unpin);
+
+        let pins = warp::path!("pins")
+            .and(warp::get())
+            .and(self.ensure_authentication().await)
+            .and(self.with_db())
+            .and(warp::query::<HashMap<String, String>>())
+            .and_then(
+                |auth: AuthDetail, database: Database, query: HashMap<String, String>| async move {
+                    let channel = match query_u64(&query, "channel")? {
+                        Some(channel) => channel,
+                        None => {
+                            return Err(warp::reject::custom(ApiError::InvalidQuery));
+                        }
+                    };
+                    handlers::chat::pins(auth, database, channel).await
+                },
+            );
+
+        let thread = warp::path!("thread")
+            .and(warp::get())
+            .and(self.ensure_authentication().await)
+            .and(self.with_db())
+            .and(warp::query::<HashMap<String, String>>())
+            .and_then(
+                |auth: AuthDetail, database: Database, query: HashMap<String, String>| async move {
+                    let message_id = match query_u64(&query, "id")? {
+                        Some(message_id) => message_id,
+                        None => {
+                            return Err(warp::reject::custom(ApiError::InvalidQuery));
+                        }
+                    };
+                    handlers::chat::thread(auth, database, message_id).await
+                },
+            );
+
+        let edit = warp::path!("edit")
+            .and(warp::post())
+            .and(self.ensure_authentication().await)
+            .and(json_body::<MessageEditData>())
+            .and(self.with_db())
+            .and(self.with_ws_connections())
+            .and(self.with_chat_settings())
+            .and_then(handlers::chat::edit);
+
+        let delete = warp::path!("delete")
+            .and(warp::post())
+            .and(self.ensure_authentication().await)
+            .and(json_body::<MessageDeleteData>())
+            .and(self.with_db())
+            .and(self.with_ws_connections())
+            .and_then(handlers::chat::delete);
+
+        let reaction_add = warp::path!("reaction" / "add")
+            .and(warp::post())
+            .and(self.ensure_authentication().await)
+            .and(json_body::<ReactionData>())
+            .and(self.with_db())
+            .and(self.with_ws_connections())
+            .and_then(handlers::chat::add_reaction);
+
+        let reaction_remove = warp::path!("reaction" / "remove")
+            .and(warp::post())
+            .and(self.ensure_authentication().await)
+            .and(json_body::<ReactionData>())
+            .and(self.with_db())
+            .and(self.with_ws_connections())
+            .and_then(handlers::chat::remove_reaction);
+
+        let dm_open = warp::path!("dm" / "open")
+            .and(warp::post())
+            .and(self.ensure_authentication().await)
+            .and(json_body::<DmOpenData>())
+            .and(self.with_db())
+            .and_then(handlers::dm::open);
+
+        let dm_list = warp::path!("dm" / "list")
+            .and(warp::get())
+            .and(self.ensure_authentication().await)
+            .and(self.with_db())
+            .and_then(handlers::dm::list);
+
+        let read = warp::path!("read")
+            .and(warp::post())
+            .and(self.ensure_authentication().await)
+            .and(json_body::<ReadMarkerData>())
+            .and(self.with_db())
+            .and(self.with_ws_connections())
+            .and_then(handlers::chat::read);
+
+        let unread = warp::path!("unread")
+            .and(warp::get())
+            .and(self.ensure_authentication().await)
+            .and(self.with_db())
+            .and_then(handlers::chat::unread);
+
+        let status = warp::path!("status")
+            .and(warp::post())
+            .and(self.ensure_authentication().await)
+            .and(json_body::<StatusData>())
+            .and(self.with_db())
+            .and(self.with_ws_connections())
+            .and(self.with_chat_settings())
+            .and_then(handlers::presence::set_status);
+
+        let mention_list = warp::path!("mention" / "list")
+            .and(warp::get())
+            .and(self.ensure_authentication().await)
+            .and(self.with_db())
+            .and_then(handlers::mention::list);
+
+        let mention_read = warp::path!("mention" / "read")
+            .and(warp::post())
+            .and(self.ensure_authentication().await)
+            .and(json_body::<MentionReadData>())
+            .and(self.with_db())
+            .and_then(handlers::mention::read);
+
+        let schedule = warp::path!("schedule")
+            .and(warp::post())
+            .and(self.ensure_authentication().await)
+            .and(json_body::<ScheduleData>())
+            .and(self.with_db())
+            .and(self.with_chat_settings())
+            .and_then(handlers::schedule::create);
+
+        let schedule_list = warp::path!("schedule" / "list")
+            .and(warp::get())
+            .and(self.ensure_authentication().await)
+            .and(self.with_db())
+            .and_then(handlers::schedule::list);
+
+        let schedule_cancel = warp::path!("schedule" / "cancel")
+            .and(warp::post())
+            .and(self.ensure_authentication().await)
+            .and(json_body::<ScheduleCancelData>())
+            .and(self.with_db())
+            .and_then(handlers::schedule::cancel);
+
         let ws = warp::path!("ws")
             .and(warp::query::<HashMap<String, String>>())
             .and(warp::ws())
             .and(self.with_ws_connections())
             .and(self.with_db())
+            .and(self.with_chat_settings())
             .and_then(
                 |query: HashMap<String, String>,
                  ws: warp::ws::Ws,
                  connections: Connections,
-                 database: Database| async move {
+                 database: Database,
+                 settings: ChatSettings| async move {
                     let token = match query.get("token") {
                         Some(token) => token,
                         None => {
@@ -227,14 +586,56 @@ impl Api {
                             return Err(warp::reject::custom(ApiError::NotAuthorized));
                         }
                     };
+                    // A token in use can only take over a connection that is still registered
+                    if token_info.in_use && connections.read().await.get(token).is_none() {
+                        return Err(warp::reject::custom(ApiError::NotAuthorized));
+                    }
 
-                    let res = ws.on_upgrade(move |socket| {
-                        handlers::chat::ws(socket, connections, database, token_info)
-                    });
+                    let last_seq = query_u64(&query, "last_seq")?;
+
+                    // Leave room for JSON escaping and the rest of the frame
+                    let max_frame_bytes = settings.max_message_bytes * 4 + 1024;
+                    let res = ws
+                        .max_message_size(max_frame_bytes)
+                        .max_frame_size(max_frame_bytes)
+                        .on_upgrade(move |socket| {
+                            handlers::chat::ws(
+                                socket,
+                                connections,
+                                database,
+                                token_info,
+                                last_seq,
+                                settings,
+                            )
+                        });
                     Ok(res)
                 },
             );
-        prefix.and(ws.or(token))
+        prefix.and(
+            ws.or(token)
+                .or(history)
+                .or(search)
+                .or(pin)
+                .or(unpin)
+                .or(pins)
+                .or(upload)
+                .or(download)
+                .or(thread)
+                .or(edit)
+                .or(delete)
+                .or(reaction_add)
+                .or(reaction_remove)
+                .or(dm_open)
+                .or(dm_list)
+                .or(read)
+                .or(unread)
+                .or(status)
+                .or(mention_list)
+                .or(mention_read)
+                .or(schedule)
+                .or(schedule_list)
+                .or(schedule_cancel),
+        )
     }
 
     pub async fn server(
@@ -256,6 +657,14 @@ impl Api {
             .and(self.with_db())
             .and_then(handlers::server::search);
 
+        let presence = warp::path("presence")
+            .and(warp::post())
+            .and(self.ensure_authentication().await)
+            .and(json_body::<ServerPresenceData>())
+            .and(self.with_db())
+            .and(self.with_ws_connections())
+            .and_then(handlers::presence::list);
+
         let get_invite_code = warp::path("get_invite_code")
             .and(warp::post())
             .and(self.ensure_authentication().await)
@@ -284,10 +693,56 @@ impl Api {
             .and(self.with_db())
             .and_then(handlers::server::modify);
 
+        let channel_prefix = warp::path("channel");
+
+        let channel_create = warp::path("create")
+            .and(warp::post())
+            .and(self.ensure_authentication().await)
+            .and(json_body::<ChannelCreateData>())
+            .and(self.with_db())
+            .and_then(handlers::channel::create);
+
+        let channel_list = warp::path("list")
+            .and(warp::post())
+            .and(self.ensure_authentication().await)
+            .and(json_body::<ChannelListData>())
+            .and(self.with_db())
+            .and_then(handlers::channel::list);
+
+        let channel_rename = warp::path("rename")
+            .and(warp::post())
+            .and(self.ensure_authentication().await)
+            .and(json_body::<ChannelRenameData>())
+            .and(self.with_db())
+            .and_then(handlers::channel::rename);
+
+        let channel_delete = warp::path("delete")
+            .and(warp::post())
+            .and(self.ensure_authentication().await)
+            .and(json_body::<ChannelDeleteData>())
+            .and(self.with_db())
+            .and_then(handlers::channel::delete);
+
+        let channel_slow_mode = warp::path("slow_mode")
+            .and(warp::post())
+            .and(self.ensure_authentication().await)
+            .and(json_body::<ChannelSlowModeData>())
+            .and(self.with_db())
+            .and_then(handlers::channel::slow_mode);
+
+        let channel = channel_prefix.and(
+            channel_create
+                .or(channel_list)
+                .or(channel_rename)
+                .or(channel_delete)
+                .or(channel_slow_mode),
+        );
+
         prefix.and(
             join.or(search)
+                .or(presence)
                 .or(get_invite_code)
-                .or(sub_prefix.and(create.or(delete).or(modify))),
+                .or(sub_prefix.and(create.or(delete).or(modify).or(channel))),
         )
     }
 
@@ -325,6 +780,9 @@ impl Api {
             // When the body could not be deserialized correctly
             title = "Bad Request";
             status = StatusCode::BAD_REQUEST;
+        } else if err.find::<warp::reject::PayloadTooLarge>().is_some() {
+            title = "Payload Too Large";
+            status = StatusCode::PAYLOAD_TOO_LARGE;
         } else if let Some(e) = err.find::<ApiError>() {
             match e {
                 ApiError::NotAuthorized => {
@@ -340,6 +798,10 @@ impl Api {
                     title = "Bad Request";
                     status = StatusCode::BAD_REQUEST;
                 }
+                ApiError::StorageError => {
+                    title = "Internal Server Error";
+                    status = StatusCode::INTERNAL_SERVER_ERROR;
+                }
             }
         } else {
             title = "Unhandled Rejection";
@@ -372,6 +834,21 @@ impl Api {
         warp::any().map(move || database.clone())
     }
 
+    fn with_chat_settings(
+        &self,
+    ) -> impl Filter<Extract = (ChatSettings,), Error = std::convert::Infallible> + Clone {
+        let settings = self.chat_settings.clone();
+        warp::any().map(move || settings.clone())
+    }
+
+    fn with_attachment_settings(
+        &self,
+    ) -> impl Filter<Extract = (AttachmentSettings,), Error = std::convert::Infallible> + Clone
+    {
+        let settings = self.attachment_settings.clone();
+        warp::any().map(move || settings.clone())
+    }
+
     fn with_ws_connections(
         &self,
     ) -> impl Filter<Extract = (Connections,), Error = std::convert::Infallible> + Clone {
@@ -384,3 +861,14 @@ fn json_body<T: DeserializeOwned + Send + 'static>(
 ) -> impl Filter<Extract = (T,), Error = warp::Rejection> + Clone {
     warp::body::content_length_limit(1024 * 16).and(warp::body::json())
 }
+
+/// Reads an optional numeric query parameter, rejecting values that don't parse.
+fn query_u64(query: &HashMap<String, String>, key: &str) -> Result<Option<u64>, warp::Rejection> {
+    match query.get(key) {
+        Some(value) => match value.parse::<u64>() {
+            Ok(value) => Ok(Some(value)),
+            Err(_) => Err(warp::reject::custom(ApiError::InvalidQuery)),
+        },
+        None => Ok(None),
+    }
+}
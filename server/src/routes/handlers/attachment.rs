@@ -0,0 +1,200 @@
+use crate::configuration::AttachmentSettings;
+use crate::db::Database;
+use crate::models::chat::Attachment;
+use crate::routes::*;
+
+use futures_util::TryStreamExt;
+use rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use tokio::io::AsyncWriteExt;
+use tokio_util::io::ReaderStream;
+use warp::hyper::body::Buf;
+use warp::hyper::{Body, Response, StatusCode};
+use warp::multipart::{FormData, Part};
+use warp::reject::Rejection;
+use warp::Reply;
+
+const MAX_FILENAME_LENGTH: usize = 255;
+
+fn invalid_file(reason: &str) -> Rejection {
+    let invalid_params_vec: Vec<InvalidParamsDetail> = vec![InvalidParamsDetail {
+        name: "file".to_string(),
+        reason: reason.to_string(),
+    }];
+    warp::reject::custom(ApiError::NotProcessable(invalid_params_vec))
+}
+
+fn storage_error(error: std::io::Error) -> Rejection {
+    eprintln!("Failed to store attachment: {}", error);
+    warp::reject::custom(ApiError::StorageError)
+}
+
+/// Writes the file part to `temp_path` while hashing it, returns its hash and size.
+async fn receive_file(
+    part: Part,
+    temp_path: &Path,
+    max_size_byte: u64,
+) -> Result<(String, u64), Rejection> {
+    let mut file = tokio::fs::File::create(temp_path)
+        .await
+        .map_err(storage_error)?;
+    let mut hasher = Sha256::new();
+    let mut size: u64 = 0;
+    let mut stream = part.stream();
+    while let Some(buf) = stream
+        .try_next()
+        .await
+        .map_err(|_| invalid_file("malformed multipart body"))?
+    {
+        size += buf.chunk().len() as u64;
+        if size > max_size_byte {
+            return Err(invalid_file("file too large"));
+        }
+        hasher.update(buf.chunk());
+        file.write_all(buf.chunk()).await.map_err(storage_error)?;
+    }
+    file.sync_all().await.map_err(storage_error)?;
+
+    Ok((format!("{:x}", hasher.finalize()), size))
+}
+
+/// Moves a received file to `path` unless an identical one is already there.
+async fn store_file(temp_path: &Path, path: &Path) -> std::io::Result<()> {
+    if tokio::fs::try_exists(path).await? {
+        return tokio::fs::remove_file(temp_path).await;
+    }
+    tokio::fs::rename(temp_path, path).await
+}
+
+/// Keeps only the last path component of a client supplied file name, without
+/// characters that could break the download header or a terminal.
+fn clean_filename(filename: &str) -> String {
+    let name = filename.rsplit(['/', '\\']).next().unwrap_or_default();
+    let mut cleaned = String::new();
+    for c in name.chars().filter(|c| !c.is_control() && *c != '"') {
+        if cleaned.len() + c.len_utf8() > MAX_FILENAME_LENGTH {
+            break;
+        }
+        cleaned.push(c);
+    }
+
+    match cleaned.trim() {
+        "" | "." | ".." => "attachment".to_string(),
+        cleaned => cleaned.to_string(),
+    }
+}
+
+pub async fn upload(
+    auth: AuthDetail,
+    database: Database,
+    settings: AttachmentSettings,
+    channel: u64,
+    mut form: FormData,
+) -> Result<impl warp::Reply, Rejection> {
+    if !database.can_access_channel(auth.id, channel).await {
+        return Err(warp::reject::custom(ApiError::NotAuthorized));
+    }
+
+    // find the file part of the form
+    let part = loop {
+        match form.try_next().await {
+            Ok(Some(part)) if part.name() == "file" => break part,
+            Ok(Some(_)) => continue,
+            Ok(None) => return Err(invalid_file("missing file part")),
+            Err(_) => return Err(invalid_file("malformed multipart body")),
+        }
+    };
+
+    let filename = clean_filename(part.filename().unwrap_or_default());
+    let content_type = part
+        .content_type()
+        .unwrap_or("application/octet-stream")
+        .to_string();
+    if !settings.is_allowed_type(&content_type) {
+        return Err(invalid_file("content type not allowed"));
+    }
+
+    // receive into a temporary file first, so a partial file is never served
+    let directory = Path::new(&settings.directory);
+    tokio::fs::create_dir_all(directory)
+        .await
+        .map_err(storage_error)?;
+    let temp_path = directory.join(format!("{:016x}.part", OsRng.next_u64()));
+    let res = match receive_file(part, &temp_path, settings.max_size_byte).await {
+        // store the file under its hash, identical uploads share one file
+        Ok((hash, size)) => store_file(&temp_path, &directory.join(&hash))
+            .await
+            .map(|()| (hash, size))
+            .map_err(storage_error),
+        Err(e) => Err(e),
+    };
+    if res.is_err() {
+        let _ = tokio::fs::remove_file(&temp_path).await;
+    }
+    let (hash, size) = res?;
+
+    let id = database
+        .insert_attachment(channel, auth.id, &filename, &content_type, size, &hash)
+        .await;
+
+    let response = Attachment {
+        id,
+        channel,
+        user_id: auth.id,
+        filename,
+        content_type,
+        size,
+        hash,
+    };
+    Ok(warp::reply::json(&response))
+}
+
+pub async fn download(
+    auth: AuthDetail,
+    database: Database,
+    settings: AttachmentSettings,
+    attachment_id: u64,
+) -> Result<impl warp::Reply, Rejection> {
+    let attachment = match database.get_attachment(attachment_id).await {
+        Some(attachment) => attachment,
+        None => {
+            let invalid_params_vec: Vec<InvalidParamsDetail> = vec![InvalidParamsDetail {
+                name: "id".to_string(),
+                reason: "No such attachment".to_string(),
+            }];
+            return Err(warp::reject::custom(ApiError::NotProcessable(
+                invalid_params_vec,
+            )));
+        }
+    };
+
+    // check if user can still see the channel it was shared in
+    if !database
+        .can_access_channel(auth.id, attachment.channel)
+        .await
+    {
+        return Err(warp::reject::custom(ApiError::NotAuthorized));
+    }
+
+    let path = Path::new(&settings.directory).join(&attachment.hash);
+    let file = match tokio::fs::File::open(path).await {
+        Ok(file) => file,
+        Err(_) => {
+            return Err(warp::reject::not_found());
+        }
+    };
+
+    let res = Response::builder()
+        .status(StatusCode::OK)
+        .header(warp::http::header::CONTENT_TYPE, attachment.content_type)
+        .header(
+            warp::http::header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", attachment.filename),
+        )
+        .header(warp::http::header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+        .header(warp::http::header::CONTENT_LENGTH, attachment.size)
+        .body(Body::wrap_stream(ReaderStream::new(file)))
+        .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+    Ok(res)
+}
@@ -114,7 +114,7 @@ pub async fn login(
         refresh_token,
     };
 
-    return Ok(warp::reply::json(&response));
+    Ok(warp::reply::json(&response))
 }
 
 pub async fn refresh(
@@ -205,7 +205,7 @@ pub async fn refresh(
         session,
         refresh_token,
     };
-    return Ok(warp::reply::json(&response));
+    Ok(warp::reply::json(&response))
 }
 
 pub async fn signup(
@@ -215,6 +215,17 @@ pub async fn signup(
     let username = json_data.clone().username;
     let pw = json_data.pw;
 
+    // usernames are shown on every terminal the user chats with
+    if username.chars().any(char::is_control) {
+        let invalid_params_vec: Vec<InvalidParamsDetail> = vec![InvalidParamsDetail {
+            name: "username".to_string(),
+            reason: "must not contain control characters".to_string(),
+        }];
+        return Err(warp::reject::custom(ApiError::NotProcessable(
+            invalid_params_vec,
+        )));
+    }
+
     // check if username is already in the database
     let mut conn = database.pool.get_conn().unwrap();
     let result: Vec<Row> = conn
@@ -0,0 +1,216 @@
+use crate::db::Database;
+use crate::routes::*;
+
+use mysql::{params, prelude::Queryable, Row};
+use serde::Serialize;
+use warp::reject::Rejection;
+
+const MAX_SLOW_MODE_SECOND: u32 = 6 * 60 * 60;
+
+#[derive(Serialize)]
+pub struct ChannelData {
+    id: u64,
+    server_id: u64,
+    name: String,
+}
+
+fn check_channel_name(name: &str) -> Result<(), Rejection> {
+    if name.len() > 32 || name.is_empty() {
+        let invalid_params_vec: Vec<InvalidParamsDetail> = vec![InvalidParamsDetail {
+            name: "name".to_string(),
+            reason: "length out of range".to_string(),
+        }];
+        return Err(warp::reject::custom(ApiError::NotProcessable(
+            invalid_params_vec,
+        )));
+    }
+    // names are printed as they are on every member's terminal
+    if name.chars().any(char::is_control) {
+        let invalid_params_vec: Vec<InvalidParamsDetail> = vec![InvalidParamsDetail {
+            name: "name".to_string(),
+            reason: "must not contain control characters".to_string(),
+        }];
+        return Err(warp::reject::custom(ApiError::NotProcessable(
+            invalid_params_vec,
+        )));
+    }
+    Ok(())
+}
+
+/// Looks up the server of a channel and checks that the user is one of its members.
+async fn check_channel_authority(
+    database: &Database,
+    user_id: u64,
+    channel_id: u64,
+) -> Result<u64, Rejection> {
+    let server_id = match database.get_channel_server(channel_id).await {
+        Some(server_id) => server_id,
+        None => {
+            let invalid_params_vec: Vec<InvalidParamsDetail> = vec![InvalidParamsDetail {
+                name: "id".to_string(),
+                reason: "No such channel".to_string(),
+            }];
+            return Err(warp::reject::custom(ApiError::NotProcessable(
+                invalid_params_vec,
+            )));
+        }
+    };
+
+    if !database.is_server_member(server_id, user_id).await {
+        return Err(warp::reject::custom(ApiError::NotAuthorized));
+    }
+
+    Ok(server_id)
+}
+
+pub async fn create(
+    auth: AuthDetail,
+    json_data: ChannelCreateData,
+    database: Database,
+) -> Result<impl warp::Reply, Rejection> {
+    let user_id = auth.id;
+    let server_id = json_data.server_id;
+    let channel_name = json_data.name;
+
+    check_channel_name(&channel_name)?;
+
+    // check if user has authority
+    if !database.is_server_member(server_id, user_id).await {
+        return Err(warp::reject::custom(ApiError::NotAuthorized));
+    }
+
+    // add channel info to channel table
+    let mut conn = database.pool.get_conn().unwrap();
+    conn.exec::<Row, _, _>(
+        "INSERT INTO channel (server_id, name) VALUES (:server_id, :name)",
+        params! {
+            "server_id" => server_id,
+            "name" => channel_name.clone(),
+        },
+    )
+    .unwrap();
+
+    let response = ChannelData {
+        id: conn.last_insert_id(),
+        server_id,
+        name: channel_name,
+    };
+
+    Ok(warp::reply::json(&response))
+}
+
+pub async fn list(
+    auth: AuthDetail,
+    json_data: ChannelListData,
+    database: Database,
+) -> Result<impl warp::Reply, Rejection> {
+    let user_id = auth.id;
+    let server_id = json_data.server_id;
+
+    // check if user has authority
+    if !database.is_server_member(server_id, user_id).await {
+        return Err(warp::reject::custom(ApiError::NotAuthorized));
+    }
+
+    let mut conn = database.pool.get_conn().unwrap();
+    let result: Vec<Row> = conn
+        .exec(
+            "SELECT id, name FROM channel WHERE server_id = :server_id ORDER BY id",
+            params! {
+                "server_id" => server_id,
+            },
+        )
+        .unwrap();
+
+    let mut channel_list: Vec<ChannelData> = Vec::new();
+    for row in result {
+        let (id, name): (u64, String) = mysql::from_row(row);
+        channel_list.push(ChannelData {
+            id,
+            server_id,
+            name,
+        });
+    }
+
+    Ok(warp::reply::json(&channel_list))
+}
+
+pub async fn rename(
+    auth: AuthDetail,
+    json_data: ChannelRenameData,
+    database: Database,
+) -> Result<impl warp::Reply, Rejection> {
+    let user_id = auth.id;
+    let channel_id = json_data.id;
+    let channel_name = json_data.name;
+
+    check_channel_name(&channel_name)?;
+
+    // only server admins may rename channels
+    let server_id = check_channel_authority(&database, user_id, channel_id).await?;
+    if !database.is_server_admin(server_id, user_id).await {
+        return Err(warp::reject::custom(ApiError::NotAuthorized));
+    }
+
+    // modify channel info
+    let mut conn = database.pool.get_conn().unwrap();
+    conn.exec::<Row, _, _>(
+        "UPDATE channel SET name = :name WHERE id = :id",
+        params! {
+            "name" => channel_name,
+            "id" => channel_id,
+        },
+    )
+    .unwrap();
+
+    Ok(warp::reply())
+}
+
+pub async fn delete(
+    auth: AuthDetail,
+    json_data: ChannelDeleteData,
+    database: Database,
+) -> Result<impl warp::Reply, Rejection> {
+    let user_id = auth.id;
+    let channel_id = json_data.id;
+
+    // only server admins may delete channels and their history
+    let server_id = check_channel_authority(&database, user_id, channel_id).await?;
+    if !database.is_server_admin(server_id, user_id).await {
+        return Err(warp::reject::custom(ApiError::NotAuthorized));
+    }
+
+    database.delete_channel(channel_id).await;
+
+    Ok(warp::reply())
+}
+
+pub async fn slow_mode(
+    auth: AuthDetail,
+    json_data: ChannelSlowModeData,
+    database: Database,
+) -> Result<impl warp::Reply, Rejection> {
+    let user_id = auth.id;
+    let channel_id = json_data.id;
+    let second = json_data.second;
+
+    if second > MAX_SLOW_MODE_SECOND {
+        let invalid_params_vec: Vec<InvalidParamsDetail> = vec![InvalidParamsDetail {
+            name: "second".to_string(),
+            reason: "out of range".to_string(),
+        }];
+        return Err(warp::reject::custom(ApiError::NotProcessable(
+            invalid_params_vec,
+        )));
+    }
+
+    // only server admins may change slow mode
+    let server_id = check_channel_authority(&database, user_id, channel_id).await?;
+    if !database.is_server_admin(server_id, user_id).await {
+        return Err(warp::reject::custom(ApiError::NotAuthorized));
+    }
+
+    database.set_slow_mode(channel_id, second).await;
+
+    Ok(warp::reply())
+}
@@ -1,27 +1,60 @@
 use std::time::SystemTime;
 
-use crate::db::Database;
-use crate::models::chat::{ChatTokenInfo, ChatTokenResponse, MessageKind};
-use crate::models::chat::{Connection, Connections};
-use crate::routes::AuthDetail;
+use crate::configuration::ChatSettings;
+use crate::db::{Database, HistoryCursor, SearchFilter, EVENT_RETENTION};
+use crate::models::chat::{
+    ChatHistoryResponse, ChatMessage, ChatThreadResponse, ChatTokenInfo, ChatTokenResponse,
+    ClientMessage, MessageKind, PresenceState,
+};
+use crate::models::chat::{Connection, Connections, OutboundQueue, Registry};
+use crate::routes::handlers::presence;
+use crate::routes::{
+    ApiError, AuthDetail, InvalidParamsDetail, MessageDeleteData, MessageEditData, PinData,
+    ReactionData, ReadMarkerData,
+};
+use crate::sanitize::sanitize;
 use crate::utils;
+use crate::validate::{validate_content, ContentError};
 
 use futures_util::{SinkExt, StreamExt};
 use mysql::prelude::Queryable;
 use mysql::{params, Row};
 use rand_core::{OsRng, RngCore};
-use tokio::sync::mpsc;
-use tokio_stream::wrappers::UnboundedReceiverStream;
+use std::sync::Arc;
+use tokio::time::{interval_at, sleep_until, Duration, Instant};
 use warp::ws::{Message, WebSocket};
 
 const CHAT_TOKEN_EXPIRE_MINUTE: u64 = 5;
+const CHAT_TOKEN_RESUME_MINUTE: u64 = 5;
+const HISTORY_DEFAULT_LIMIT: u64 = 50;
+const HISTORY_MAX_LIMIT: u64 = 100;
+const SEARCH_DEFAULT_LIMIT: u64 = 25;
+const MAX_SEARCH_QUERY_LENGTH: usize = 256;
+const TYPING_EXPIRE_SECOND: u64 = 5;
+const MAX_NONCE_LENGTH: usize = 64;
+const MAX_MENTIONS: usize = 20;
+const MAX_PINS_PER_CHANNEL: u64 = 50;
+const MAX_EMOJI_LENGTH: usize = 32;
 
 pub async fn chat_token(
     auth: AuthDetail,
     database: Database,
     channel: u64,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    // TODO: add check if channel is valid
+    // check if channel is valid and user may access it
+    if !database.channel_exists(channel).await {
+        let invalid_params_vec: Vec<InvalidParamsDetail> = vec![InvalidParamsDetail {
+            name: "channel".to_string(),
+            reason: "No such channel".to_string(),
+        }];
+        return Err(warp::reject::custom(ApiError::NotProcessable(
+            invalid_params_vec,
+        )));
+    }
+    if !database.can_access_channel(auth.id, channel).await {
+        return Err(warp::reject::custom(ApiError::NotAuthorized));
+    }
+
     let mut conn = database.pool.get_conn().unwrap();
 
     let mut key = OsRng.next_u64().to_le_bytes().to_vec();
@@ -53,66 +86,1196 @@ pub async fn chat_token(
     Ok(warp::reply::json(&response))
 }
 
+pub async fn history(
+    auth: AuthDetail,
+    database: Database,
+    channel: u64,
+    cursor: HistoryCursor,
+    limit: Option<u64>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let limit = limit
+        .unwrap_or(HISTORY_DEFAULT_LIMIT)
+        .clamp(1, HISTORY_MAX_LIMIT);
+
+    if !database.can_access_channel(auth.id, channel).await {
+        return Err(warp::reject::custom(ApiError::NotAuthorized));
+    }
+
+    let (messages, has_more) = database.get_history(channel, cursor, limit).await;
+
+    let response = ChatHistoryResponse { messages, has_more };
+    Ok(warp::reply::json(&response))
+}
+
+pub async fn search(
+    auth: AuthDetail,
+    database: Database,
+    filter: SearchFilter,
+    limit: Option<u64>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let limit = limit
+        .unwrap_or(SEARCH_DEFAULT_LIMIT)
+        .clamp(1, HISTORY_MAX_LIMIT);
+
+    let query_length = filter.query.trim().chars().count();
+    if query_length == 0 || query_length > MAX_SEARCH_QUERY_LENGTH {
+        let invalid_params_vec: Vec<InvalidParamsDetail> = vec![InvalidParamsDetail {
+            name: "query".to_string(),
+            reason: "length out of range".to_string(),
+        }];
+        return Err(warp::reject::custom(ApiError::NotProcessable(
+            invalid_params_vec,
+        )));
+    }
+
+    // Natural language queries take any text, but don't let a query MySQL
+    // still refuses take the handler down
+    let (messages, has_more) = match database.search_messages(auth.id, &filter, limit).await {
+        Ok(result) => result,
+        Err(_) => {
+            let invalid_params_vec: Vec<InvalidParamsDetail> = vec![InvalidParamsDetail {
+                name: "query".to_string(),
+                reason: "not a valid search query".to_string(),
+            }];
+            return Err(warp::reject::custom(ApiError::NotProcessable(
+                invalid_params_vec,
+            )));
+        }
+    };
+
+    let response = ChatHistoryResponse { messages, has_more };
+    Ok(warp::reply::json(&response))
+}
+
+/// Records an event in the channel's log and sends it, tagged with its sequence
+/// number, to the channel's subscribers except `except_token`.
+pub(crate) async fn publish(
+    connections: &Connections,
+    database: &Database,
+    channel: u64,
+    event: &MessageKind,
+    except_token: &str,
+) {
+    let sequencer = connections.read().await.sequencer(channel);
+    let _sequenced = sequencer.lock().await;
+    // Nobody can follow a deleted channel, so there is nothing to deliver
+    let seq = match database.record_event(channel, event).await {
+        Some(seq) => seq,
+        None => {
+            return;
+        }
+    };
+    connections
+        .read()
+        .await
+        .publish(channel, seq, event, except_token);
+}
+
+/// Why an action on a message, such as posting, an edit, reaction or pin, was refused.
+pub(crate) enum MessageActionError {
+    NoSuchMessage,
+    NotAuthor,
+    NoAccess,
+    InvalidEmoji,
+    NoSuchAttachment,
+    InvalidContent(ContentError),
+    NoPinRights,
+    TooManyPins,
+}
+
+impl MessageActionError {
+    fn code(&self) -> &'static str {
+        match self {
+            MessageActionError::NoSuchMessage => "no_such_message",
+            MessageActionError::NotAuthor
+            | MessageActionError::NoAccess
+            | MessageActionError::NoPinRights => "not_authorized",
+            MessageActionError::TooManyPins => "too_many_pins",
+            MessageActionError::InvalidEmoji => "invalid_params",
+            MessageActionError::NoSuchAttachment => "no_such_attachment",
+            MessageActionError::InvalidContent(e) => e.code(),
+        }
+    }
+
+    fn reason(&self) -> String {
+        match self {
+            MessageActionError::NoSuchMessage => "No such message".to_string(),
+            MessageActionError::NotAuthor => "Only the author can change a message".to_string(),
+            MessageActionError::NoAccess => "Cannot access channel".to_string(),
+            MessageActionError::InvalidEmoji => {
+                "Emoji must be 1 to 32 non-whitespace characters".to_string()
+            }
+            MessageActionError::NoSuchAttachment => "No such attachment".to_string(),
+            MessageActionError::InvalidContent(e) => e.reason(),
+            MessageActionError::NoPinRights => "Only server admins can pin messages".to_string(),
+            MessageActionError::TooManyPins => {
+                format!("A channel can have at most {} pins", MAX_PINS_PER_CHANNEL)
+            }
+        }
+    }
+
+    pub(crate) fn into_rejection(self) -> warp::Rejection {
+        let name = match self {
+            MessageActionError::NoSuchMessage | MessageActionError::TooManyPins => "id",
+            MessageActionError::InvalidEmoji => "emoji",
+            MessageActionError::NoSuchAttachment => "attachment_id",
+            MessageActionError::InvalidContent(_) => "msg",
+            MessageActionError::NotAuthor
+            | MessageActionError::NoAccess
+            | MessageActionError::NoPinRights => {
+                return warp::reject::custom(ApiError::NotAuthorized);
+            }
+        };
+        let invalid_params_vec: Vec<InvalidParamsDetail> = vec![InvalidParamsDetail {
+            name: name.to_string(),
+            reason: self.reason(),
+        }];
+        warp::reject::custom(ApiError::NotProcessable(invalid_params_vec))
+    }
+}
+
+/// Checks the attachment and text of a new message from `user_id` to `channel`.
+/// Returns the text sanitized for storing and relaying.
+pub(crate) async fn check_new_message(
+    database: &Database,
+    settings: &ChatSettings,
+    user_id: u64,
+    channel: u64,
+    content: &str,
+    attachment_id: Option<u64>,
+) -> Result<String, MessageActionError> {
+    // Attachments can only be sent by their uploader, to the channel they were uploaded to
+    if let Some(attachment_id) = attachment_id {
+        match database.get_attachment(attachment_id).await {
+            Some(attachment) if attachment.channel == channel && attachment.user_id == user_id => {}
+            _ => {
+                return Err(MessageActionError::NoSuchAttachment);
+            }
+        }
+    }
+
+    // Never relay or store anything a terminal would interpret
+    let content = sanitize(content, settings.sanitize_policy);
+    // A bare attachment needs no text
+    if !(attachment_id.is_some() && content.is_empty()) {
+        validate_content(&content, settings).map_err(MessageActionError::InvalidContent)?;
+    }
+    Ok(content)
+}
+
+/// Checks that `user_id` wrote the message and may still see its channel.
+/// Returns the channel of the message.
+async fn check_message_author(
+    database: &Database,
+    user_id: u64,
+    message_id: u64,
+) -> Result<u64, MessageActionError> {
+    let (channel, author) = match database.get_message_info(message_id).await {
+        Some(info) => info,
+        None => {
+            return Err(MessageActionError::NoSuchMessage);
+        }
+    };
+    if author != user_id || !database.can_access_channel(user_id, channel).await {
+        return Err(MessageActionError::NotAuthor);
+    }
+    Ok(channel)
+}
+
+async fn edit_message(
+    connections: &Connections,
+    database: &Database,
+    user_id: u64,
+    message_id: u64,
+    content: String,
+    settings: &ChatSettings,
+) -> Result<(), MessageActionError> {
+    let content = sanitize(&content, settings.sanitize_policy);
+    validate_content(&content, settings).map_err(MessageActionError::InvalidContent)?;
+
+    let channel = check_message_author(database, user_id, message_id).await?;
+    let edited = database.edit_message(message_id, content.clone()).await;
+    let edited_msg = MessageKind::Edited {
+        message_id,
+        msg: content,
+        edited,
+    };
+    publish(connections, database, channel, &edited_msg, "").await;
+
+    Ok(())
+}
+
+async fn delete_message(
+    connections: &Connections,
+    database: &Database,
+    user_id: u64,
+    message_id: u64,
+) -> Result<(), MessageActionError> {
+    let channel = check_message_author(database, user_id, message_id).await?;
+
+    database.delete_message(message_id).await;
+    let deleted_msg = MessageKind::Deleted { message_id };
+    publish(connections, database, channel, &deleted_msg, "").await;
+
+    Ok(())
+}
+
+/// Adds or removes a reaction of `user_id` and broadcasts the new count.
+async fn react(
+    connections: &Connections,
+    database: &Database,
+    user_id: u64,
+    message_id: u64,
+    emoji: String,
+    added: bool,
+) -> Result<(), MessageActionError> {
+    if emoji.is_empty()
+        || emoji.chars().count() > MAX_EMOJI_LENGTH
+        || emoji.chars().any(|c| c.is_whitespace() || c.is_control())
+    {
+        return Err(MessageActionError::InvalidEmoji);
+    }
+
+    let channel = match database.get_message_info(message_id).await {
+        Some((channel, _)) => channel,
+        None => {
+            return Err(MessageActionError::NoSuchMessage);
+        }
+    };
+    if !database.can_access_channel(user_id, channel).await {
+        return Err(MessageActionError::NoAccess);
+    }
+
+    let changed = match added {
+        true => database.add_reaction(message_id, user_id, &emoji).await,
+        false => database.remove_reaction(message_id, user_id, &emoji).await,
+    };
+    // Adding a reaction twice or removing a missing one is not worth announcing
+    if !changed {
+        return Ok(());
+    }
+
+    let count = database.count_reaction(message_id, &emoji).await;
+    let reaction_msg = MessageKind::Reaction {
+        message_id,
+        id: user_id,
+        emoji,
+        added,
+        count,
+    };
+    publish(connections, database, channel, &reaction_msg, "").await;
+
+    Ok(())
+}
+
+/// Announces a newly stored message to the channel and notifies the users it
+/// mentions.
+pub(crate) async fn post_message(
+    connections: &Connections,
+    database: &Database,
+    message: &ChatMessage,
+    except_token: &str,
+) {
+    let new_msg = MessageKind::Chat {
+        id: message.user_id,
+        username: message.username.clone(),
+        msg: message.msg.clone(),
+        message_id: message.id,
+        timestamp: message.timestamp,
+        parent_id: message.parent_id,
+        attachment_id: message.attachment_id,
+        expires: message.expires,
+    };
+    publish(
+        connections,
+        database,
+        message.channel,
+        &new_msg,
+        except_token,
+    )
+    .await;
+
+    let usernames: Vec<String> = utils::parse_mentions(&message.msg)
+        .into_iter()
+        .take(MAX_MENTIONS)
+        .collect();
+    let mut user_ids = database.resolve_mentions(message.channel, &usernames).await;
+    user_ids.retain(|user_id| *user_id != message.user_id);
+    if user_ids.is_empty() {
+        return;
+    }
+
+    database
+        .insert_mentions(message.id, message.channel, &user_ids)
+        .await;
+    let mention_msg = MessageKind::Mention {
+        message_id: message.id,
+        channel: message.channel,
+        id: message.user_id,
+        username: message.username.clone(),
+        msg: message.msg.clone(),
+        timestamp: message.timestamp,
+    };
+    let connections = connections.read().await;
+    for user_id in user_ids {
+        connections.notify_user(user_id, &mention_msg);
+    }
+}
+
+/// Pins or unpins a message and broadcasts the change.
+async fn set_pin(
+    connections: &Connections,
+    database: &Database,
+    user_id: u64,
+    message_id: u64,
+    pinned: bool,
+) -> Result<(), MessageActionError> {
+    let channel = match database.get_message_info(message_id).await {
+        Some((channel, _)) => channel,
+        None => {
+            return Err(MessageActionError::NoSuchMessage);
+        }
+    };
+    if !database.can_pin(user_id, channel).await {
+        return Err(MessageActionError::NoPinRights);
+    }
+
+    let changed = match pinned {
+        true => match database
+            .add_pin(channel, message_id, user_id, MAX_PINS_PER_CHANNEL)
+            .await
+        {
+            Some(changed) => changed,
+            None => {
+                return Err(MessageActionError::TooManyPins);
+            }
+        },
+        false => database.remove_pin(message_id).await,
+    };
+    // Pinning twice or unpinning a message that isn't pinned changes nothing
+    if !changed {
+        return Ok(());
+    }
+
+    let pin_msg = MessageKind::Pin {
+        message_id,
+        id: user_id,
+        pinned,
+    };
+    publish(connections, database, channel, &pin_msg, "").await;
+
+    Ok(())
+}
+
+pub async fn pin(
+    auth: AuthDetail,
+    json_data: PinData,
+    database: Database,
+    connections: Connections,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    set_pin(&connections, &database, auth.id, json_data.id, true)
+        .await
+        .map_err(MessageActionError::into_rejection)?;
+
+    Ok(warp::reply())
+}
+
+pub async fn unpin(
+    auth: AuthDetail,
+    json_data: PinData,
+    database: Database,
+    connections: Connections,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    set_pin(&connections, &database, auth.id, json_data.id, false)
+        .await
+        .map_err(MessageActionError::into_rejection)?;
+
+    Ok(warp::reply())
+}
+
+pub async fn pins(
+    auth: AuthDetail,
+    database: Database,
+    channel: u64,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if !database.can_access_channel(auth.id, channel).await {
+        return Err(warp::reject::custom(ApiError::NotAuthorized));
+    }
+
+    let messages = database.get_pins(channel).await;
+
+    Ok(warp::reply::json(&messages))
+}
+
+/// Moves the user's read marker in the message's channel up to the message and
+/// tells the user's other connections about it.
+async fn mark_read(
+    connections: &Connections,
+    database: &Database,
+    user_id: u64,
+    message_id: u64,
+) -> Result<(), MessageActionError> {
+    let channel = match database.get_message_info(message_id).await {
+        Some((channel, _)) => channel,
+        None => {
+            return Err(MessageActionError::NoSuchMessage);
+        }
+    };
+    if !database.can_access_channel(user_id, channel).await {
+        return Err(MessageActionError::NoAccess);
+    }
+
+    let message_id = database.set_read_marker(user_id, channel, message_id).await;
+    let read_msg = MessageKind::ReadMarker {
+        channel,
+        message_id,
+    };
+    connections.read().await.notify_user(user_id, &read_msg);
+
+    Ok(())
+}
+
+pub async fn add_reaction(
+    auth: AuthDetail,
+    json_data: ReactionData,
+    database: Database,
+    connections: Connections,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    react(
+        &connections,
+        &database,
+        auth.id,
+        json_data.id,
+        json_data.emoji,
+        true,
+    )
+    .await
+    .map_err(MessageActionError::into_rejection)?;
+
+    Ok(warp::reply())
+}
+
+pub async fn remove_reaction(
+    auth: AuthDetail,
+    json_data: ReactionData,
+    database: Database,
+    connections: Connections,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    react(
+        &connections,
+        &database,
+        auth.id,
+        json_data.id,
+        json_data.emoji,
+        false,
+    )
+    .await
+    .map_err(MessageActionError::into_rejection)?;
+
+    Ok(warp::reply())
+}
+
+pub async fn read(
+    auth: AuthDetail,
+    json_data: ReadMarkerData,
+    database: Database,
+    connections: Connections,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    mark_read(&connections, &database, auth.id, json_data.id)
+        .await
+        .map_err(MessageActionError::into_rejection)?;
+
+    Ok(warp::reply())
+}
+
+pub async fn unread(
+    auth: AuthDetail,
+    database: Database,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let unread_counts = database.get_unread_counts(auth.id).await;
+
+    Ok(warp::reply::json(&unread_counts))
+}
+
+pub async fn edit(
+    auth: AuthDetail,
+    json_data: MessageEditData,
+    database: Database,
+    connections: Connections,
+    settings: ChatSettings,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    edit_message(
+        &connections,
+        &database,
+        auth.id,
+        json_data.id,
+        json_data.msg,
+        &settings,
+    )
+    .await
+    .map_err(MessageActionError::into_rejection)?;
+
+    Ok(warp::reply())
+}
+
+pub async fn delete(
+    auth: AuthDetail,
+    json_data: MessageDeleteData,
+    database: Database,
+    connections: Connections,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    delete_message(&connections, &database, auth.id, json_data.id)
+        .await
+        .map_err(MessageActionError::into_rejection)?;
+
+    Ok(warp::reply())
+}
+
+/// Announces the user of a connection that is gone as leaving every channel
+/// they have no other connection in.
+fn announce_leave(connections: &Registry, connection: &Connection, token: &str) {
+    for channel in connection.channels.iter() {
+        if !connections.is_user_in_channel(connection.id, *channel, token) {
+            let leave_msg = MessageKind::Leave {
+                id: connection.id,
+                username: connection.username.clone(),
+            };
+            connections.broadcast(*channel, &leave_msg, token);
+        }
+    }
+}
+
+/// State of one websocket connection while it is open.
+struct Session {
+    connections: Connections,
+    database: Database,
+    token: String,
+    /// Queue of this connection, tells it apart from a newer one with the same token
+    queue: Arc<OutboundQueue>,
+    id: u64,
+    username: String,
+    channel: u64,
+    /// When set, the user is typing until this deadline unless they refresh it
+    typing_deadline: Option<Instant>,
+    /// When to check whether the user went idle, pushed back by activity
+    idle_deadline: Option<Instant>,
+    settings: ChatSettings,
+}
+
+impl Session {
+    /// Adds a channel to the connection's subscriptions, sends it a member
+    /// snapshot and announces the user if this is their first connection there.
+    /// With `last_seq`, logged events after it are replayed first.
+    async fn subscribe(&self, channel: u64, last_seq: Option<u64>) {
+        // Holding the channel's sequencer keeps new events from being published
+        // between reading the missed ones and subscribing, without stalling
+        // fan-out in other channels during the database reads
+        let sequencer = self.connections.read().await.sequencer(channel);
+        let _sequenced = sequencer.lock().await;
+        let replay = match last_seq {
+            Some(last_seq) => {
+                let latest_seq = self.database.get_last_seq(channel).await;
+                let events = self
+                    .database
+                    .get_events_after(channel, last_seq, EVENT_RETENTION)
+                    .await;
+                Some((last_seq, latest_seq, events))
+            }
+            None => None,
+        };
+
+        let mut connections = self.connections.write().await;
+        if !connections.subscribe(&self.token, channel) {
+            return;
+        }
+
+        if let (Some((last_seq, latest_seq, events)), Some(connection)) =
+            (replay, connections.get(&self.token))
+        {
+            let complete = events.len() as u64 == latest_seq.saturating_sub(last_seq);
+            for (seq, event) in events.iter() {
+                connection.send_sequenced(channel, *seq, event);
+            }
+            let resumed_msg = MessageKind::Resumed {
+                seq: latest_seq,
+                complete,
+            };
+            connection.send(channel, &resumed_msg);
+        }
+
+        // Give the newcomer a snapshot of who is in the channel
+        let members = connections.channel_members(channel);
+        if let Some(connection) = connections.get(&self.token) {
+            connection.send(channel, &MessageKind::Members { members });
+        }
+
+        // Only announce the user once, no matter how many sockets they open
+        if !connections.is_user_in_channel(self.id, channel, &self.token) {
+            let join_msg = MessageKind::Join {
+                id: self.id,
+                username: self.username.clone(),
+            };
+            connections.broadcast(channel, &join_msg, &self.token);
+        }
+    }
+
+    /// Drops a channel from the connection's subscriptions, announcing the
+    /// user's departure if no other connection of theirs remains there.
+    async fn unsubscribe(&mut self, channel: u64) {
+        if channel == self.channel {
+            self.set_typing(false).await;
+        }
+
+        let mut connections = self.connections.write().await;
+        if !connections.unsubscribe(&self.token, channel) {
+            return;
+        }
+
+        if !connections.is_user_in_channel(self.id, channel, &self.token) {
+            let leave_msg = MessageKind::Leave {
+                id: self.id,
+                username: self.username.clone(),
+            };
+            connections.broadcast(channel, &leave_msg, &self.token);
+        }
+    }
+
+    /// Leaves every subscribed channel and forgets the connection. Returns
+    /// false if a resumed connection took over the token in the meantime.
+    async fn close(&mut self) -> bool {
+        self.set_typing(false).await;
+        let went_offline = {
+            let mut connections = self.connections.write().await;
+            let connection = match connections.remove(&self.token, &self.queue) {
+                Some(connection) => connection,
+                None => {
+                    return false;
+                }
+            };
+            announce_leave(&connections, &connection, &self.token);
+            connections.presence(self.id) == PresenceState::Offline
+        };
+        if went_offline {
+            presence::announce(&self.connections, &self.database, self.id, &self.username).await;
+        }
+        true
+    }
+
+    /// Records activity on this connection, bringing the user back from idle.
+    async fn touch(&mut self) {
+        let now = Instant::now();
+        self.idle_deadline = Some(now + Duration::from_secs(self.settings.idle_after_second));
+        let was_idle = self.connections.write().await.touch(self.id, now);
+        if was_idle {
+            presence::announce(&self.connections, &self.database, self.id, &self.username).await;
+        }
+    }
+
+    async fn check_idle(&mut self) {
+        self.idle_deadline = None;
+        let idle_after = Duration::from_secs(self.settings.idle_after_second);
+        let went_idle =
+            self.connections
+                .write()
+                .await
+                .mark_idle(self.id, Instant::now(), idle_after);
+        if went_idle {
+            presence::announce(&self.connections, &self.database, self.id, &self.username).await;
+        }
+    }
+
+    async fn is_subscribed(&self, channel: u64) -> bool {
+        match self.connections.read().await.get(&self.token) {
+            Some(connection) => connection.channels.contains(&channel),
+            None => false,
+        }
+    }
+
+    async fn notify(&self, message: &MessageKind) {
+        if let Some(connection) = self.connections.read().await.get(&self.token) {
+            connection.notify(message);
+        }
+    }
+
+    async fn notify_error(&self, code: &str, reason: &str) {
+        let error_msg = MessageKind::Error {
+            code: code.to_string(),
+            reason: reason.to_string(),
+        };
+        self.notify(&error_msg).await;
+    }
+
+    async fn set_typing(&mut self, typing: bool) {
+        let was_typing = self.typing_deadline.is_some();
+        self.typing_deadline = match typing {
+            true => Some(Instant::now() + Duration::from_secs(TYPING_EXPIRE_SECOND)),
+            false => None,
+        };
+
+        // Refreshing the deadline doesn't need to be announced again
+        if typing != was_typing {
+            let typing_msg = MessageKind::Typing {
+                id: self.id,
+                username: self.username.clone(),
+                typing,
+            };
+            self.connections
+                .read()
+                .await
+                .broadcast(self.channel, &typing_msg, &self.token);
+        }
+    }
+
+    async fn handle_frame(&mut self, frame: &str) {
+        let client_msg = match serde_json::from_str::<ClientMessage>(frame) {
+            Ok(client_msg) => client_msg,
+            Err(e) => {
+                self.notify_error("malformed_frame", &e.to_string()).await;
+                return;
+            }
+        };
+
+        match client_msg {
+            ClientMessage::Send {
+                msg,
+                parent_id,
+                channel,
+                nonce,
+                attachment_id,
+                ttl_second,
+            } => {
+                let channel = channel.unwrap_or(self.channel);
+                self.send_chat(channel, msg, parent_id, nonce, attachment_id, ttl_second)
+                    .await
+            }
+            ClientMessage::Edit { message_id, msg } => {
+                let res = edit_message(
+                    &self.connections,
+                    &self.database,
+                    self.id,
+                    message_id,
+                    msg,
+                    &self.settings,
+                )
+                .await;
+                if let Err(e) = res {
+                    self.notify_error(e.code(), &e.reason()).await;
+                }
+            }
+            ClientMessage::Delete { message_id } => {
+                let res =
+                    delete_message(&self.connections, &self.database, self.id, message_id).await;
+                if let Err(e) = res {
+                    self.notify_error(e.code(), &e.reason()).await;
+                }
+            }
+            ClientMessage::ReactionAdd { message_id, emoji } => {
+                self.react(message_id, emoji, true).await
+            }
+            ClientMessage::ReactionRemove { message_id, emoji } => {
+                self.react(message_id, emoji, false).await
+            }
+            ClientMessage::MarkRead { message_id } => {
+                let res = mark_read(&self.connections, &self.database, self.id, message_id).await;
+                if let Err(e) = res {
+                    self.notify_error(e.code(), &e.reason()).await;
+                }
+            }
+            ClientMessage::TypingStart => self.set_typing(true).await,
+            ClientMessage::TypingStop => self.set_typing(false).await,
+            ClientMessage::Subscribe { channel, last_seq } => {
+                if self.check_access(channel).await {
+                    self.subscribe(channel, last_seq).await;
+                }
+            }
+            ClientMessage::Unsubscribe { channel } => self.unsubscribe(channel).await,
+            ClientMessage::SwitchChannel { channel } => self.switch_channel(channel).await,
+            ClientMessage::Ping => self.notify(&MessageKind::Pong).await,
+        }
+    }
+
+    async fn send_chat(
+        &mut self,
+        channel: u64,
+        content: String,
+        parent_id: Option<u64>,
+        nonce: Option<String>,
+        attachment_id: Option<u64>,
+        ttl_second: Option<u64>,
+    ) {
+        if let Some(ttl_second) = ttl_second {
+            if ttl_second == 0 || ttl_second > self.settings.max_message_ttl_second {
+                let reason = format!(
+                    "Time-to-live must be 1 to {} s",
+                    self.settings.max_message_ttl_second
+                );
+                self.notify_error("invalid_ttl", &reason).await;
+                return;
+            }
+        }
+
+        if let Some(nonce) = &nonce {
+            if nonce.is_empty() || nonce.len() > MAX_NONCE_LENGTH {
+                self.notify_error("invalid_nonce", "Nonce must be 1 to 64 bytes")
+                    .await;
+                return;
+            }
+
+            // A retry of a message that already went through only gets its ack again
+            let since = SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs()
+                .saturating_sub(self.settings.dedup_window_second);
+            if let Some((message_id, timestamp)) = self
+                .database
+                .find_message_by_nonce(self.id, nonce, since)
+                .await
+            {
+                let ack_msg = MessageKind::Ack {
+                    nonce: nonce.clone(),
+                    message_id,
+                    timestamp,
+                };
+                self.notify(&ack_msg).await;
+                return;
+            }
+            // Outside the window the nonce counts as new
+            self.database.release_nonce(self.id, nonce, since).await;
+        }
+
+        if !self.is_subscribed(channel).await {
+            self.notify_error("not_subscribed", "Subscribe to the channel first")
+                .await;
+            return;
+        }
+        // Access may have been lost, or the channel deleted, since subscribing
+        if !self.check_access(channel).await {
+            return;
+        }
+
+        let res = self.connections.write().await.take_send_token(
+            self.id,
+            self.settings.rate_limit_burst,
+            self.settings.rate_limit_per_second,
+            Instant::now(),
+        );
+        if let Err(retry_after) = res {
+            let reason = format!("Sending too fast, retry in {} ms", retry_after.as_millis());
+            self.notify_error("rate_limited", &reason).await;
+            return;
+        }
+
+        let wait = self.database.slow_mode_wait(channel, self.id).await;
+        if wait > 0 {
+            let reason = format!("Slow mode is on, retry in {} s", wait);
+            self.notify_error("slow_mode", &reason).await;
+            return;
+        }
+
+        // Replies must stay within the channel of their parent
+        if let Some(parent_id) = parent_id {
+            match self.database.get_message_info(parent_id).await {
+                Some((parent_channel, _)) if parent_channel == channel => {}
+                _ => {
+                    self.notify_error("no_such_message", "No such parent message")
+                        .await;
+                    return;
+                }
+            }
+        }
+
+        let res = check_new_message(
+            &self.database,
+            &self.settings,
+            self.id,
+            channel,
+            &content,
+            attachment_id,
+        )
+        .await;
+        let content = match res {
+            Ok(content) => content,
+            Err(e) => {
+                self.notify_error(e.code(), &e.reason()).await;
+                return;
+            }
+        };
+
+        // A chat message ends the typing state without a separate announcement
+        if channel == self.channel {
+            self.typing_deadline = None;
+        }
+
+        let res = self
+            .database
+            .insert_message(
+                channel,
+                self.id,
+                content.clone(),
+                parent_id,
+                nonce.clone(),
+                attachment_id,
+            )
+            .await;
+        let (message_id, timestamp) = match (res, nonce.clone()) {
+            (Ok(inserted), _) => inserted,
+            // A retry that raced the first attempt only gets its ack
+            (Err((message_id, timestamp)), Some(nonce)) => {
+                let ack_msg = MessageKind::Ack {
+                    nonce,
+                    message_id,
+                    timestamp,
+                };
+                self.notify(&ack_msg).await;
+                return;
+            }
+            (Err(_), None) => {
+                return;
+            }
+        };
+        let expires = ttl_second.map(|ttl_second| timestamp + ttl_second);
+        if let Some(expires) = expires {
+            self.database.set_message_expiry(message_id, expires).await;
+        }
+        if let Some(nonce) = nonce {
+            let ack_msg = MessageKind::Ack {
+                nonce,
+                message_id,
+                timestamp,
+            };
+            self.notify(&ack_msg).await;
+        }
+        let message = ChatMessage {
+            id: message_id,
+            channel,
+            user_id: self.id,
+            username: self.username.clone(),
+            msg: content,
+            timestamp,
+            edited: None,
+            parent_id,
+            attachment_id,
+            expires,
+            reactions: Vec::new(),
+        };
+        post_message(&self.connections, &self.database, &message, &self.token).await;
+    }
+
+    async fn react(&self, message_id: u64, emoji: String, added: bool) {
+        let res = react(
+            &self.connections,
+            &self.database,
+            self.id,
+            message_id,
+            emoji,
+            added,
+        )
+        .await;
+        if let Err(e) = res {
+            self.notify_error(e.code(), &e.reason()).await;
+        }
+    }
+
+    async fn check_access(&self, channel: u64) -> bool {
+        if !self.database.can_access_channel(self.id, channel).await {
+            self.notify_error("not_authorized", "Cannot access channel")
+                .await;
+            return false;
+        }
+        true
+    }
+
+    async fn switch_channel(&mut self, channel: u64) {
+        if channel == self.channel {
+            return;
+        }
+        if !self.check_access(channel).await {
+            return;
+        }
+
+        self.unsubscribe(self.channel).await;
+        self.channel = channel;
+        self.subscribe(channel, None).await;
+    }
+}
+
+pub async fn thread(
+    auth: AuthDetail,
+    database: Database,
+    message_id: u64,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let parent = match database.get_message(message_id).await {
+        Some(parent) => parent,
+        None => {
+            let invalid_params_vec: Vec<InvalidParamsDetail> = vec![InvalidParamsDetail {
+                name: "id".to_string(),
+                reason: "No such message".to_string(),
+            }];
+            return Err(warp::reject::custom(ApiError::NotProcessable(
+                invalid_params_vec,
+            )));
+        }
+    };
+
+    if !database.can_access_channel(auth.id, parent.channel).await {
+        return Err(warp::reject::custom(ApiError::NotAuthorized));
+    }
+
+    let replies = database.get_replies(message_id).await;
+
+    let response = ChatThreadResponse { parent, replies };
+    Ok(warp::reply::json(&response))
+}
+
 pub async fn ws(
     websocket: WebSocket,
     connections: Connections,
     database: Database,
     token_info: ChatTokenInfo,
+    last_seq: Option<u64>,
+    settings: ChatSettings,
 ) {
     let (mut ws_tx, mut ws_rx) = websocket.split();
-    let (tx, rx) = mpsc::unbounded_channel::<Message>();
+    let queue = Arc::new(OutboundQueue::new(
+        settings.queue_depth,
+        settings.queue_full_policy,
+        connections.read().await.dropped_counter(),
+    ));
 
     // When receive message send it to the user
+    let writer_queue = queue.clone();
     tokio::task::spawn(async move {
-        let mut rx = UnboundedReceiverStream::new(rx);
-        while let Some(message) = rx.next().await {
-            ws_tx.send(message).await.unwrap_or(());
+        while let Some(message) = writer_queue.pop().await {
+            if ws_tx.send(message).await.is_err() {
+                break;
+            }
         }
+        // Make the session notice a dead socket even if the client never reads
+        writer_queue.close();
     });
 
-    let username = match database.get_username(token_info.id).await {
-        Some(username) => username,
-        None => {
-            return;
+    let mut session = Session {
+        connections,
+        database,
+        token: token_info.token,
+        queue: queue.clone(),
+        id: token_info.id,
+        username: token_info.username.clone(),
+        channel: token_info.channel,
+        typing_deadline: None,
+        idle_deadline: None,
+        settings: settings.clone(),
+    };
+    let came_online = {
+        let mut connections = session.connections.write().await;
+        let was_offline = connections.presence(session.id) == PresenceState::Offline;
+        let replaced = connections.insert(
+            session.token.clone(),
+            Connection::new(token_info.id, token_info.username, queue.clone()),
+        );
+        // Resuming with a token still in use drops the stale connection
+        if let Some(replaced) = replaced {
+            replaced.sender.close();
+            announce_leave(&connections, &replaced, &session.token);
         }
+        was_offline
     };
+    session.touch().await;
+    if came_online {
+        presence::announce(
+            &session.connections,
+            &session.database,
+            session.id,
+            &session.username,
+        )
+        .await;
+    }
+    session.subscribe(token_info.channel, last_seq).await;
 
-    connections.write().await.insert(
-        token_info.token.clone(),
-        Connection::new(token_info.id, username.clone(), token_info.channel, tx),
-    );
+    let ping_period = Duration::from_secs(settings.ping_interval_second.max(1));
+    let mut ping_interval = interval_at(Instant::now() + ping_period, ping_period);
+    // When set, a ping is outstanding and must be answered before this deadline
+    let mut pong_deadline: Option<Instant> = None;
 
-    while let Some(res) = ws_rx.next().await {
+    loop {
+        let res = tokio::select! {
+            res = ws_rx.next() => res,
+            _ = queue.closed() => {
+                break;
+            }
+            _ = ping_interval.tick() => {
+                if pong_deadline.is_none() {
+                    queue.push(Message::ping(Vec::new()));
+                    pong_deadline =
+                        Some(Instant::now() + Duration::from_secs(settings.pong_timeout_second));
+                }
+                continue;
+            }
+            _ = sleep_until(pong_deadline.unwrap_or_else(Instant::now)), if pong_deadline.is_some() => {
+                // The client stopped answering, treat it like a close
+                break;
+            }
+            _ = sleep_until(session.typing_deadline.unwrap_or_else(Instant::now)), if session.typing_deadline.is_some() => {
+                session.set_typing(false).await;
+                continue;
+            }
+            _ = sleep_until(session.idle_deadline.unwrap_or_else(Instant::now)), if session.idle_deadline.is_some() => {
+                session.check_idle().await;
+                continue;
+            }
+        };
         let msg = match res {
-            Ok(msg) => msg,
-            Err(_) => {
+            Some(Ok(msg)) => msg,
+            _ => {
                 break;
             }
         };
         if msg.is_close() {
             break;
         }
-        if msg.is_text() {
-            let new_msg = MessageKind::Chat {
-                id: token_info.id,
-                username: username.clone(),
-                msg: msg.to_str().unwrap().to_owned(),
-            };
-            for (from_token, connection) in connections.read().await.iter() {
-                if token_info.token == *from_token {
-                    continue;
-                }
-                connection.send(token_info.channel, &new_msg);
-            }
+        if msg.is_pong() {
+            pong_deadline = None;
+        } else if msg.is_text() {
+            session.touch().await;
+            session.handle_frame(msg.to_str().unwrap_or_default()).await;
+        } else if msg.is_binary() {
+            session
+                .notify_error("malformed_frame", "Binary frames are not supported")
+                .await;
         }
     }
 
     // Cleanup
-    connections.write().await.remove(&token_info.token);
-    let mut conn = database.pool.get_conn().unwrap();
+    let was_current = session.close().await;
+    queue.close();
+    if queue.dropped() > 0 {
+        eprintln!(
+            "Connection of user {} dropped {} messages, {} dropped across all connections",
+            session.id,
+            queue.dropped(),
+            session.connections.read().await.dropped_messages()
+        );
+    }
+
+    // The token now belongs to the connection that replaced this one
+    if !was_current {
+        return;
+    }
+
+    // Let the client resume with the same token for a while
+    let expire = SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        + 60 * CHAT_TOKEN_RESUME_MINUTE;
+    let mut conn = session.database.pool.get_conn().unwrap();
     conn.exec::<Row, _, _>(
-        "DELETE FROM chat_token WHERE chat_token = :chat_token",
-        params! {"chat_token" => token_info.token},
+        "UPDATE chat_token SET is_used = FALSE, expire = :expire WHERE chat_token = :chat_token",
+        params! {
+            "expire" => expire,
+            "chat_token" => session.token,
+        },
     )
     .unwrap();
 }
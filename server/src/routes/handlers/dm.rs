@@ -0,0 +1,69 @@
+use crate::db::Database;
+use crate::routes::*;
+
+use warp::reject::Rejection;
+
+const MAX_DM_PARTICIPANTS: usize = 10;
+
+pub async fn open(
+    auth: AuthDetail,
+    json_data: DmOpenData,
+    database: Database,
+) -> Result<impl warp::Reply, Rejection> {
+    let user_id = auth.id;
+
+    // resolve participants, always including the caller
+    let mut participants: Vec<u64> = vec![user_id];
+    let mut invalid_params_vec: Vec<InvalidParamsDetail> = Vec::new();
+    for username in json_data.usernames.iter() {
+        match database.get_user_id(username).await {
+            Some(id) => participants.push(id),
+            None => invalid_params_vec.push(InvalidParamsDetail {
+                name: "usernames".to_string(),
+                reason: format!("No such user: {}", username),
+            }),
+        }
+    }
+    participants.sort_unstable();
+    participants.dedup();
+
+    if participants.len() < 2 || participants.len() > MAX_DM_PARTICIPANTS {
+        invalid_params_vec.push(InvalidParamsDetail {
+            name: "usernames".to_string(),
+            reason: "number of participants out of range".to_string(),
+        });
+    }
+    if !invalid_params_vec.is_empty() {
+        return Err(warp::reject::custom(ApiError::NotProcessable(
+            invalid_params_vec,
+        )));
+    }
+
+    // reuse the conversation if these exact participants already have one
+    let conversations = database.get_dm_conversations(user_id).await;
+    let existing = conversations.into_iter().find(|conversation| {
+        conversation
+            .participants
+            .iter()
+            .map(|member| member.id)
+            .eq(participants.iter().copied())
+    });
+    if let Some(conversation) = existing {
+        return Ok(warp::reply::json(&conversation));
+    }
+
+    let channel = database.create_dm(&participants).await;
+    let conversation = database
+        .get_dm_conversations(user_id)
+        .await
+        .into_iter()
+        .find(|conversation| conversation.id == channel);
+
+    Ok(warp::reply::json(&conversation))
+}
+
+pub async fn list(auth: AuthDetail, database: Database) -> Result<impl warp::Reply, Rejection> {
+    let conversations = database.get_dm_conversations(auth.id).await;
+
+    Ok(warp::reply::json(&conversations))
+}
@@ -0,0 +1,24 @@
+use crate::db::Database;
+use crate::routes::*;
+
+use warp::reject::Rejection;
+
+const MENTION_LIST_LIMIT: u64 = 100;
+
+pub async fn list(auth: AuthDetail, database: Database) -> Result<impl warp::Reply, Rejection> {
+    let mentions = database
+        .get_unread_mentions(auth.id, MENTION_LIST_LIMIT)
+        .await;
+
+    Ok(warp::reply::json(&mentions))
+}
+
+pub async fn read(
+    auth: AuthDetail,
+    json_data: MentionReadData,
+    database: Database,
+) -> Result<impl warp::Reply, Rejection> {
+    database.mark_mentions_read(auth.id, &json_data.ids).await;
+
+    Ok(warp::reply())
+}
@@ -1,6 +1,12 @@
 mod health_check;
 
 pub use health_check::*;
+pub mod attachment;
 pub mod auth;
+pub mod channel;
 pub mod chat;
+pub mod dm;
+pub mod mention;
+pub mod presence;
+pub mod schedule;
 pub mod server;
@@ -0,0 +1,98 @@
+use crate::configuration::ChatSettings;
+use crate::db::Database;
+use crate::models::chat::{Connections, MemberPresence, MessageKind};
+use crate::routes::*;
+use crate::sanitize::sanitize;
+
+use warp::reject::Rejection;
+
+const MAX_STATUS_TEXT_LENGTH: usize = 128;
+
+/// Pushes the user's current presence to everyone sharing a server with them.
+pub async fn announce(
+    connections: &Connections,
+    database: &Database,
+    user_id: u64,
+    username: &str,
+) {
+    let state = connections.read().await.presence(user_id);
+    let (status, text) = database.get_user_status(user_id).await;
+    let presence_msg = MessageKind::Presence {
+        id: user_id,
+        username: username.to_string(),
+        state,
+        status,
+        text,
+    };
+
+    let peers = database.get_server_peers(user_id).await;
+    let connections = connections.read().await;
+    for peer in peers {
+        connections.notify_user(peer, &presence_msg);
+    }
+}
+
+pub async fn set_status(
+    auth: AuthDetail,
+    json_data: StatusData,
+    database: Database,
+    connections: Connections,
+    settings: ChatSettings,
+) -> Result<impl warp::Reply, Rejection> {
+    let user_id = auth.id;
+    let text = json_data
+        .text
+        .map(|text| sanitize(&text, settings.sanitize_policy))
+        .filter(|text| !text.trim().is_empty());
+
+    if let Some(text) = &text {
+        if text.chars().count() > MAX_STATUS_TEXT_LENGTH || text.contains('\n') {
+            let invalid_params_vec: Vec<InvalidParamsDetail> = vec![InvalidParamsDetail {
+                name: "text".to_string(),
+                reason: "must be a single line of at most 128 characters".to_string(),
+            }];
+            return Err(warp::reject::custom(ApiError::NotProcessable(
+                invalid_params_vec,
+            )));
+        }
+    }
+
+    database
+        .set_user_status(user_id, json_data.status, text)
+        .await;
+
+    if let Some(username) = database.get_username(user_id).await {
+        announce(&connections, &database, user_id, &username).await;
+    }
+
+    Ok(warp::reply())
+}
+
+pub async fn list(
+    auth: AuthDetail,
+    json_data: ServerPresenceData,
+    database: Database,
+    connections: Connections,
+) -> Result<impl warp::Reply, Rejection> {
+    let server_id = json_data.id;
+
+    // check if user has authority
+    if !database.is_server_member(server_id, auth.id).await {
+        return Err(warp::reject::custom(ApiError::NotAuthorized));
+    }
+
+    let members = database.get_server_member_status(server_id).await;
+    let connections = connections.read().await;
+    let presence_list: Vec<MemberPresence> = members
+        .into_iter()
+        .map(|(member, status, text)| MemberPresence {
+            state: connections.presence(member.id),
+            id: member.id,
+            username: member.username,
+            status,
+            text,
+        })
+        .collect();
+
+    Ok(warp::reply::json(&presence_list))
+}
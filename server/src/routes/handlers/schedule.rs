@@ -0,0 +1,114 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::configuration::ChatSettings;
+use crate::db::Database;
+use crate::routes::handlers::chat::{check_new_message, MessageActionError};
+use crate::routes::*;
+
+use warp::reject::Rejection;
+
+/// How far in the future a message can be scheduled, one year
+const MAX_SCHEDULE_AHEAD_SECOND: u64 = 365 * 24 * 60 * 60;
+/// Most messages a user can have waiting to be sent
+const MAX_PENDING_PER_USER: u64 = 100;
+
+fn not_processable(name: &str, reason: String) -> Rejection {
+    let invalid_params_vec: Vec<InvalidParamsDetail> = vec![InvalidParamsDetail {
+        name: name.to_string(),
+        reason,
+    }];
+    warp::reject::custom(ApiError::NotProcessable(invalid_params_vec))
+}
+
+pub async fn create(
+    auth: AuthDetail,
+    json_data: ScheduleData,
+    database: Database,
+    settings: ChatSettings,
+) -> Result<impl warp::Reply, Rejection> {
+    let channel = json_data.channel;
+    if !database.can_access_channel(auth.id, channel).await {
+        return Err(warp::reject::custom(ApiError::NotAuthorized));
+    }
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    if json_data.send_at <= now || json_data.send_at > now + MAX_SCHEDULE_AHEAD_SECOND {
+        return Err(not_processable(
+            "send_at",
+            "must be in the future, at most a year from now".to_string(),
+        ));
+    }
+
+    if let Some(ttl_second) = json_data.ttl_second {
+        if ttl_second == 0 || ttl_second > settings.max_message_ttl_second {
+            return Err(not_processable(
+                "ttl_second",
+                format!("must be 1 to {}", settings.max_message_ttl_second),
+            ));
+        }
+    }
+
+    let content = check_new_message(
+        &database,
+        &settings,
+        auth.id,
+        channel,
+        &json_data.msg,
+        json_data.attachment_id,
+    )
+    .await
+    .map_err(MessageActionError::into_rejection)?;
+
+    let id = match database
+        .insert_scheduled_message(
+            channel,
+            auth.id,
+            content,
+            json_data.attachment_id,
+            json_data.send_at,
+            json_data.ttl_second,
+            MAX_PENDING_PER_USER,
+        )
+        .await
+    {
+        Some(id) => id,
+        None => {
+            return Err(not_processable(
+                "send_at",
+                format!(
+                    "at most {} messages can be scheduled at once",
+                    MAX_PENDING_PER_USER
+                ),
+            ));
+        }
+    };
+
+    Ok(warp::reply::json(&ScheduleResponse { id }))
+}
+
+pub async fn list(auth: AuthDetail, database: Database) -> Result<impl warp::Reply, Rejection> {
+    let messages = database.get_scheduled_messages(auth.id).await;
+
+    Ok(warp::reply::json(&messages))
+}
+
+pub async fn cancel(
+    auth: AuthDetail,
+    json_data: ScheduleCancelData,
+    database: Database,
+) -> Result<impl warp::Reply, Rejection> {
+    if !database
+        .delete_scheduled_message(json_data.id, Some(auth.id))
+        .await
+    {
+        return Err(not_processable(
+            "id",
+            "No such scheduled message".to_string(),
+        ));
+    }
+
+    Ok(warp::reply())
+}
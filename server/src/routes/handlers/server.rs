@@ -125,13 +125,34 @@ pub async fn get_invite_code(
         .unwrap();
 
     let invite_code: String = mysql::from_row(result[0].clone());
-    let response = InviteCodeData {
-        invite_code: invite_code,
-    };
+    let response = InviteCodeData { invite_code };
 
     Ok(warp::reply::json(&response))
 }
 
+fn check_server_name(name: &str) -> Result<(), Rejection> {
+    if name.len() > 32 || name.is_empty() {
+        let invalid_params_vec: Vec<InvalidParamsDetail> = vec![InvalidParamsDetail {
+            name: "name".to_string(),
+            reason: "length out of range".to_string(),
+        }];
+        return Err(warp::reject::custom(ApiError::NotProcessable(
+            invalid_params_vec,
+        )));
+    }
+    // names are printed as they are on every member's terminal
+    if name.chars().any(char::is_control) {
+        let invalid_params_vec: Vec<InvalidParamsDetail> = vec![InvalidParamsDetail {
+            name: "name".to_string(),
+            reason: "must not contain control characters".to_string(),
+        }];
+        return Err(warp::reject::custom(ApiError::NotProcessable(
+            invalid_params_vec,
+        )));
+    }
+    Ok(())
+}
+
 pub async fn create(
     auth: AuthDetail,
     json_data: ServerCreateData,
@@ -141,16 +162,7 @@ pub async fn create(
     let server_public = json_data.public;
     let user_id = auth.id;
 
-    // check if server_name lenght is appropriate
-    if server_name.len() > 32 || server_name.len() == 0 {
-        let invalid_params_vec: Vec<InvalidParamsDetail> = vec![InvalidParamsDetail {
-            name: "name".to_string(),
-            reason: "length out of range".to_string(),
-        }];
-        return Err(warp::reject::custom(ApiError::NotProcessable(
-            invalid_params_vec,
-        )));
-    }
+    check_server_name(&server_name)?;
 
     // add server info to server table
     let mut conn = database.pool.get_conn().unwrap();
@@ -167,11 +179,11 @@ pub async fn create(
     let result: Vec<Row> = conn.exec("SELECT LAST_INSERT_ID()", ()).unwrap();
     let server_id: u64 = mysql::from_row(result[0].clone());
 
-    // add authority info to user_server_relationship table
+    // add authority info to user_server_relationship table, the creator is admin
     conn.exec::<Row, _, _>(
         "INSERT INTO
-        user_server_relationship (server_id, user_id)
-        VALUES (:server_id, :user_id)",
+        user_server_relationship (server_id, user_id, is_admin)
+        VALUES (:server_id, :user_id, TRUE)",
         params! {
             "server_id" => server_id,
             "user_id" => user_id,
@@ -230,7 +242,23 @@ pub async fn delete(
     )
     .unwrap();
 
+    // delete channels of the server
+    let result: Vec<Row> = conn
+        .exec(
+            "SELECT id FROM channel WHERE server_id = :server_id",
+            params! {
+                "server_id" => server_id,
+            },
+        )
+        .unwrap();
+    drop(conn);
+    for row in result {
+        let channel_id: u64 = mysql::from_row(row);
+        database.delete_channel(channel_id).await;
+    }
+
     // delete server from user_server_relationship table
+    let mut conn = database.pool.get_conn().unwrap();
     conn.exec::<Row, _, _>(
         "DELETE FROM user_server_relationship WHERE server_id = :server_id",
         params! {
@@ -252,6 +280,8 @@ pub async fn modify(
     let server_name = json_data.name;
     let public = json_data.public;
 
+    check_server_name(&server_name)?;
+
     // check if user has authority
     let mut conn = database.pool.get_conn().unwrap();
     let result: Vec<Row> = conn.exec(
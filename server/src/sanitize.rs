@@ -0,0 +1,129 @@
+use crate::configuration::SanitizePolicy;
+
+const ESC: char = '\u{1b}';
+const BEL: char = '\u{07}';
+/// Single-character C1 forms of the sequence introducers
+const C1_CSI: char = '\u{9b}';
+const C1_ST: char = '\u{9c}';
+const C1_OSC: char = '\u{9d}';
+/// Longest SGR sequence kept by `SanitizePolicy::AllowSgr`, in bytes
+const MAX_SGR_LENGTH: usize = 32;
+const SGR_RESET: &str = "\u{1b}[0m";
+
+/// Makes chat content safe to print on other members' terminals.
+///
+/// Newlines and tabs are kept as they are. Every other control character,
+/// including whole escape sequences, is handled according to `policy`.
+pub fn sanitize(content: &str, policy: SanitizePolicy) -> String {
+    match policy {
+        SanitizePolicy::Strip => strip(content, false),
+        SanitizePolicy::Escape => escape(content),
+        SanitizePolicy::AllowSgr => strip(content, true),
+    }
+}
+
+fn is_allowed_control(c: char) -> bool {
+    c == '\n' || c == '\t'
+}
+
+/// Removes escape sequences and control characters, keeping SGR sequences when
+/// `keep_sgr` is set. Kept colors are reset at the end so they can't leak into
+/// whatever the client prints next.
+fn strip(content: &str, keep_sgr: bool) -> String {
+    let mut output = String::with_capacity(content.len());
+    let mut chars = content.chars().peekable();
+    // Whether a kept SGR sequence may still be in effect
+    let mut styled = false;
+
+    while let Some(c) = chars.next() {
+        match c {
+            ESC => match chars.next_if(|c| matches!(c, ' '..='~')) {
+                Some('[') => {
+                    let (params, terminator) = take_csi(&mut chars);
+                    if keep_sgr && terminator == Some('m') && is_sgr_params(&params) {
+                        output.push(ESC);
+                        output.push('[');
+                        output.push_str(&params);
+                        output.push('m');
+                        styled = !(params.is_empty() || params == "0");
+                    }
+                }
+                Some(']' | 'P' | 'X' | '^' | '_') => skip_string(&mut chars),
+                // Two character escapes, possibly with intermediate bytes
+                Some(' '..='/') => {
+                    while chars.next_if(|c| matches!(c, ' '..='/')).is_some() {}
+                    chars.next_if(|c| matches!(c, '0'..='~'));
+                }
+                _ => {}
+            },
+            C1_CSI => {
+                take_csi(&mut chars);
+            }
+            C1_OSC | '\u{90}' | '\u{98}' | '\u{9e}' | '\u{9f}' => skip_string(&mut chars),
+            c if c.is_control() && !is_allowed_control(c) => {}
+            c => output.push(c),
+        }
+    }
+
+    if styled {
+        output.push_str(SGR_RESET);
+    }
+    output
+}
+
+/// Consumes the rest of a CSI sequence and returns its parameter bytes and its
+/// final byte, or `None` if the sequence is cut short.
+fn take_csi(chars: &mut std::iter::Peekable<std::str::Chars>) -> (String, Option<char>) {
+    let mut params = String::new();
+    while let Some(c) = chars.next_if(|c| matches!(c, '0'..='?')) {
+        params.push(c);
+    }
+    // Intermediate bytes never appear in SGR, remember them to reject the sequence
+    while let Some(c) = chars.next_if(|c| matches!(c, ' '..='/')) {
+        params.push(c);
+    }
+    (params, chars.next_if(|c| matches!(c, '@'..='~')))
+}
+
+fn is_sgr_params(params: &str) -> bool {
+    params.len() + 3 <= MAX_SGR_LENGTH && params.chars().all(|c| c.is_ascii_digit() || c == ';')
+}
+
+/// Consumes an OSC, DCS, SOS, PM or APC string up to and including its
+/// terminator, or to the end of the content if it is never terminated.
+fn skip_string(chars: &mut std::iter::Peekable<std::str::Chars>) {
+    while let Some(c) = chars.next() {
+        match c {
+            BEL | C1_ST => return,
+            ESC => {
+                chars.next_if_eq(&'\\');
+                return;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Replaces every control character with its caret notation, like `cat -v`,
+/// so escape sequences show up as plain text instead of being interpreted.
+fn escape(content: &str) -> String {
+    let mut output = String::with_capacity(content.len());
+
+    for c in content.chars() {
+        match c as u32 {
+            _ if is_allowed_control(c) => output.push(c),
+            code @ 0x00..=0x1f => {
+                output.push('^');
+                output.push(char::from(code as u8 + 0x40));
+            }
+            0x7f => output.push_str("^?"),
+            code @ 0x80..=0x9f => {
+                output.push_str("M-^");
+                output.push(char::from((code - 0x80) as u8 + 0x40));
+            }
+            _ => output.push(c),
+        }
+    }
+
+    output
+}
@@ -0,0 +1,148 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tokio::time::{interval, Duration, Instant, MissedTickBehavior};
+use tokio_util::sync::CancellationToken;
+
+use crate::configuration::ChatSettings;
+use crate::db::Database;
+use crate::models::chat::{ChatMessage, Connections, MessageKind, ScheduledMessage};
+use crate::routes::handlers::chat::{post_message, publish};
+
+/// Most scheduled messages posted, and expired messages deleted, per tick
+const BATCH_SIZE: u64 = 100;
+
+/// Posts scheduled messages once they are due and deletes self-destructing
+/// messages once they expire, every `period` until `shutdown` is cancelled.
+/// Scheduled messages are held to the same rate limit and slow mode as
+/// messages sent over the websocket.
+///
+/// All pending work lives in the database, so whatever came due while the
+/// server was down is handled on the first tick after a restart.
+pub async fn run(
+    connections: Connections,
+    database: Database,
+    settings: ChatSettings,
+    period: Duration,
+    shutdown: CancellationToken,
+) {
+    let mut ticker = interval(period);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => break,
+            _ = ticker.tick() => {}
+        }
+
+        // A tick that panics, e.g. on a lost database connection, is retried
+        // on the next one instead of stopping the scheduler for good
+        let tick = tokio::spawn(tick(
+            connections.clone(),
+            database.clone(),
+            settings.clone(),
+        ));
+        if let Err(error) = tick.await {
+            eprintln!("Scheduler tick failed: {}", error);
+        }
+    }
+}
+
+async fn tick(connections: Connections, database: Database, settings: ChatSettings) {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    for scheduled in database.get_due_scheduled_messages(now, BATCH_SIZE).await {
+        post_scheduled(&connections, &database, &settings, scheduled, now).await;
+    }
+    for (message_id, channel) in database.get_expired_messages(now, BATCH_SIZE).await {
+        database.delete_message(message_id).await;
+        let deleted_msg = MessageKind::Deleted { message_id };
+        publish(&connections, &database, channel, &deleted_msg, "").await;
+    }
+}
+
+async fn post_scheduled(
+    connections: &Connections,
+    database: &Database,
+    settings: &ChatSettings,
+    scheduled: ScheduledMessage,
+    now: u64,
+) {
+    // The author may have left the server since scheduling
+    if !database
+        .can_access_channel(scheduled.user_id, scheduled.channel)
+        .await
+    {
+        database.delete_scheduled_message(scheduled.id, None).await;
+        return;
+    }
+
+    // A message that has to wait is tried again once it may be sent
+    let wait = database
+        .slow_mode_wait(scheduled.channel, scheduled.user_id)
+        .await;
+    if wait > 0 {
+        database
+            .postpone_scheduled_message(scheduled.id, now + wait)
+            .await;
+        return;
+    }
+    let res = connections.write().await.take_send_token(
+        scheduled.user_id,
+        settings.rate_limit_burst,
+        settings.rate_limit_per_second,
+        Instant::now(),
+    );
+    if let Err(retry_after) = res {
+        let wait = retry_after.as_secs_f64().ceil() as u64;
+        database
+            .postpone_scheduled_message(scheduled.id, now + wait.max(1))
+            .await;
+        return;
+    }
+
+    // Claiming the message first means it is never posted twice, even if the
+    // server stops right after
+    if !database.delete_scheduled_message(scheduled.id, None).await {
+        return;
+    }
+
+    let (message_id, timestamp) = match database
+        .insert_message(
+            scheduled.channel,
+            scheduled.user_id,
+            scheduled.msg.clone(),
+            None,
+            None,
+            scheduled.attachment_id,
+        )
+        .await
+    {
+        Ok(inserted) => inserted,
+        Err(_) => {
+            return;
+        }
+    };
+    let expires = scheduled
+        .ttl_second
+        .map(|ttl_second| timestamp + ttl_second);
+    if let Some(expires) = expires {
+        database.set_message_expiry(message_id, expires).await;
+    }
+
+    let message = ChatMessage {
+        id: message_id,
+        channel: scheduled.channel,
+        user_id: scheduled.user_id,
+        username: scheduled.username,
+        msg: scheduled.msg,
+        timestamp,
+        edited: None,
+        parent_id: None,
+        attachment_id: scheduled.attachment_id,
+        expires,
+        reactions: Vec::new(),
+    };
+    post_message(connections, database, &message, "").await;
+}
@@ -1,26 +1,46 @@
 use std::future::Future;
 
 use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+use tokio::time::Duration;
 use tokio_stream::wrappers::TcpListenerStream;
+use tokio_util::sync::CancellationToken;
 use warp::Server;
 
 use crate::configuration::Settings;
 use crate::models::chat::Connections;
 use crate::routes::Api;
+use crate::scheduler;
 
+/// Builds the server and starts the message scheduler, which runs until
+/// `shutdown` is cancelled.
 async fn get_server(
     settings: Settings,
-) -> Server<
-    impl warp::Filter<Extract = (impl warp::Reply,), Error = std::convert::Infallible> + Clone,
-> {
+    shutdown: CancellationToken,
+) -> (
+    Server<
+        impl warp::Filter<Extract = (impl warp::Reply,), Error = std::convert::Infallible> + Clone,
+    >,
+    JoinHandle<()>,
+) {
     let connections = Connections::default();
-    let api = Api::new(settings, connections);
-    warp::serve(api.routes().await)
+    let period = Duration::from_secs(settings.chat.scheduler_interval_second.max(1));
+    let chat_settings = settings.chat.clone();
+    let api = Api::new(settings, connections.clone());
+    let scheduler = tokio::spawn(scheduler::run(
+        connections,
+        api.database.clone(),
+        chat_settings,
+        period,
+        shutdown,
+    ));
+    (warp::serve(api.routes().await), scheduler)
 }
 
 pub async fn run(listener: TcpListener, settings: Settings) -> impl Future<Output = ()> {
     let stream = TcpListenerStream::new(listener);
-    get_server(settings).await.serve_incoming(stream)
+    let (server, _scheduler) = get_server(settings, CancellationToken::new()).await;
+    server.serve_incoming(stream)
 }
 
 pub async fn run_with_graceful_shutdown(
@@ -29,7 +49,18 @@ pub async fn run_with_graceful_shutdown(
     settings: Settings,
 ) -> impl Future<Output = ()> {
     let stream = TcpListenerStream::new(listener);
-    get_server(settings)
-        .await
-        .serve_incoming_with_graceful_shutdown(stream, signal)
+    let shutdown = CancellationToken::new();
+    let (server, scheduler) = get_server(settings, shutdown.clone()).await;
+    let server = server.serve_incoming_with_graceful_shutdown(stream, async move {
+        signal.await;
+        shutdown.cancel();
+    });
+
+    async move {
+        server.await;
+        // Let the scheduler finish the tick it may be in
+        if let Err(error) = scheduler.await {
+            eprintln!("Scheduler stopped abnormally: {}", error);
+        }
+    }
 }
@@ -6,6 +6,22 @@ pub fn hash_from_string(input: String) -> String {
     format!("{:x}", hasher.finalize())
 }
 
+/// Returns the distinct `@username` mentions in chat content, in order of first
+/// appearance. Trailing punctuation is not part of the name.
+pub fn parse_mentions(content: &str) -> Vec<String> {
+    let mut mentions: Vec<String> = Vec::new();
+    for word in content.split_whitespace() {
+        let name = match word.trim_start_matches('(').strip_prefix('@') {
+            Some(name) => name.trim_end_matches(|c| ".,:;!?)'\"".contains(c)),
+            None => continue,
+        };
+        if !name.is_empty() && name.len() <= 32 && !mentions.iter().any(|m| m == name) {
+            mentions.push(name.to_string());
+        }
+    }
+    mentions
+}
+
 pub fn hash_from_u8(input: Vec<u8>) -> String {
     let mut hasher = Sha256::new();
     hasher.update(input);
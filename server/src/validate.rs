@@ -0,0 +1,86 @@
+use crate::configuration::{AllowedCharacters, ChatSettings};
+
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Why chat content was refused.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ContentError {
+    Empty,
+    TooManyBytes(usize),
+    TooManyGraphemes(usize),
+    TooManyLines(usize),
+    DisallowedCharacter(char),
+}
+
+impl ContentError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ContentError::Empty => "empty_message",
+            ContentError::TooManyBytes(_) | ContentError::TooManyGraphemes(_) => "message_too_long",
+            ContentError::TooManyLines(_) => "too_many_lines",
+            ContentError::DisallowedCharacter(_) => "disallowed_character",
+        }
+    }
+
+    pub fn reason(&self) -> String {
+        match self {
+            ContentError::Empty => "Message is empty".to_string(),
+            ContentError::TooManyBytes(max) => format!("Message is longer than {} bytes", max),
+            ContentError::TooManyGraphemes(max) => {
+                format!("Message is longer than {} characters", max)
+            }
+            ContentError::TooManyLines(max) => format!("Message has more than {} lines", max),
+            ContentError::DisallowedCharacter(c) => {
+                format!("Character U+{:04X} is not allowed", *c as u32)
+            }
+        }
+    }
+}
+
+/// Invisible characters that can hide or reorder text. The zero width joiner
+/// is left out since emoji sequences depend on it.
+fn is_invisible(c: char) -> bool {
+    matches!(c,
+        '\u{00ad}'
+        | '\u{200b}'..='\u{200c}'
+        | '\u{200e}'..='\u{200f}'
+        | '\u{202a}'..='\u{202e}'
+        | '\u{2060}'..='\u{2064}'
+        | '\u{2066}'..='\u{2069}'
+        | '\u{feff}'
+    )
+}
+
+fn is_allowed(c: char, allowed: AllowedCharacters) -> bool {
+    match allowed {
+        AllowedCharacters::Any => true,
+        AllowedCharacters::Visible => !is_invisible(c),
+        AllowedCharacters::Ascii => c.is_ascii_graphic() || matches!(c, ' ' | '\n' | '\t'),
+    }
+}
+
+/// Checks sanitized chat content against the configured limits.
+pub fn validate_content(content: &str, settings: &ChatSettings) -> Result<(), ContentError> {
+    if content.trim().is_empty() {
+        return Err(ContentError::Empty);
+    }
+    if content.len() > settings.max_message_bytes {
+        return Err(ContentError::TooManyBytes(settings.max_message_bytes));
+    }
+    if content.graphemes(true).count() > settings.max_message_graphemes {
+        return Err(ContentError::TooManyGraphemes(
+            settings.max_message_graphemes,
+        ));
+    }
+    if content.lines().count() > settings.max_message_lines {
+        return Err(ContentError::TooManyLines(settings.max_message_lines));
+    }
+    if let Some(c) = content
+        .chars()
+        .find(|c| !is_allowed(*c, settings.allowed_characters))
+    {
+        return Err(ContentError::DisallowedCharacter(c));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,72 @@
+use test_util::spawn_server;
+use tui_chat_server::configuration::AttachmentSettings;
+
+const SESSION: &str = "4b7275343789f043a75274f8301d325537e303cb4ac1bff6476a255815f60ac6";
+
+#[test]
+fn allowed_types_support_wildcards() {
+    let settings = AttachmentSettings {
+        directory: "attachments".to_string(),
+        max_size_byte: 1024,
+        allowed_types: vec!["text/*".to_string(), "image/png".to_string()],
+    };
+
+    assert!(settings.is_allowed_type("text/plain"));
+    assert!(settings.is_allowed_type("text/x-diff; charset=utf-8"));
+    assert!(settings.is_allowed_type("IMAGE/PNG"));
+    assert!(!settings.is_allowed_type("image/svg+xml"));
+    assert!(!settings.is_allowed_type("textfile"));
+}
+
+#[tokio::test]
+async fn upload_attachment() {
+    let (server_task, address, cancel_token) = spawn_server().await;
+    let client = reqwest::Client::new();
+
+    let part = reqwest::multipart::Part::bytes(b"--- a/x\n+++ b/x\n".to_vec())
+        .file_name("fix.patch")
+        .mime_str("text/x-diff")
+        .unwrap();
+    let form = reqwest::multipart::Form::new().part("file", part);
+
+    let response = client
+        .post(format!(
+            "http://127.0.0.1:{}/chat/attachment/upload?channel=1",
+            address.port()
+        ))
+        .header("Authorization", SESSION.to_string())
+        .multipart(form)
+        .send()
+        .await
+        .expect("Failed to send request.");
+
+    assert!(response.status().is_success());
+    println!("{:?}", response.text().await);
+
+    // Shutdown the server
+    cancel_token.cancel();
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn download_attachment() {
+    let (server_task, address, cancel_token) = spawn_server().await;
+    let client = reqwest::Client::new();
+
+    let response = client
+        .get(format!(
+            "http://127.0.0.1:{}/chat/attachment?id=1",
+            address.port()
+        ))
+        .header("Authorization", SESSION.to_string())
+        .send()
+        .await
+        .expect("Failed to send request.");
+
+    assert!(response.status().is_success());
+    println!("{:?}", response.bytes().await);
+
+    // Shutdown the server
+    cancel_token.cancel();
+    server_task.await.unwrap();
+}
@@ -0,0 +1,627 @@
+use serde::Serialize;
+use std::time::{SystemTime, UNIX_EPOCH};
+use test_util::spawn_server;
+use tui_chat_server::configuration::get_configuration;
+use tui_chat_server::db::Database;
+
+const SESSION: &str = "4b7275343789f043a75274f8301d325537e303cb4ac1bff6476a255815f60ac6";
+
+#[derive(Clone, Serialize)]
+pub struct MessageEditData {
+    pub id: u64,
+    pub msg: String,
+}
+
+#[derive(Clone, Serialize)]
+pub struct MessageDeleteData {
+    pub id: u64,
+}
+
+#[derive(Clone, Serialize)]
+pub struct DmOpenData {
+    pub usernames: Vec<String>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct StatusData {
+    pub status: Option<String>,
+    pub text: Option<String>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct ReadMarkerData {
+    pub id: u64,
+}
+
+#[derive(Clone, Serialize)]
+pub struct MentionReadData {
+    pub ids: Vec<u64>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct ScheduleData {
+    pub channel: u64,
+    pub msg: String,
+    pub send_at: u64,
+    pub attachment_id: Option<u64>,
+    pub ttl_second: Option<u64>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct ScheduleCancelData {
+    pub id: u64,
+}
+
+#[derive(Clone, Serialize)]
+pub struct PinData {
+    pub id: u64,
+}
+
+#[derive(Clone, Serialize)]
+pub struct ReactionData {
+    pub id: u64,
+    pub emoji: String,
+}
+
+#[tokio::test]
+async fn chat_history() {
+    let (server_task, address, cancel_token) = spawn_server().await;
+    let client = reqwest::Client::new();
+
+    let response = client
+        .get(format!(
+            "http://127.0.0.1:{}/chat/history?channel=1&limit=20",
+            address.port()
+        ))
+        .header("Authorization", SESSION.to_string())
+        .send()
+        .await
+        .expect("Failed to send request.");
+
+    assert!(response.status().is_success());
+    println!("{:?}", response.text().await);
+
+    // Shutdown the server
+    cancel_token.cancel();
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn chat_history_conflicting_cursor() {
+    let (server_task, address, cancel_token) = spawn_server().await;
+    let client = reqwest::Client::new();
+
+    let response = client
+        .get(format!(
+            "http://127.0.0.1:{}/chat/history?channel=1&before=10&after=2",
+            address.port()
+        ))
+        .header("Authorization", SESSION.to_string())
+        .send()
+        .await
+        .expect("Failed to send request.");
+
+    assert_eq!(response.status().as_u16(), 400);
+
+    // Shutdown the server
+    cancel_token.cancel();
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn chat_thread() {
+    let (server_task, address, cancel_token) = spawn_server().await;
+    let client = reqwest::Client::new();
+
+    let response = client
+        .get(format!(
+            "http://127.0.0.1:{}/chat/thread?id=1",
+            address.port()
+        ))
+        .header("Authorization", SESSION.to_string())
+        .send()
+        .await
+        .expect("Failed to send request.");
+
+    assert!(response.status().is_success());
+    println!("{:?}", response.text().await);
+
+    // Shutdown the server
+    cancel_token.cancel();
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn edit_message() {
+    let (server_task, address, cancel_token) = spawn_server().await;
+    let client = reqwest::Client::new();
+
+    let map = MessageEditData {
+        id: 1,
+        msg: "edited".to_string(),
+    };
+
+    let response = client
+        .post(format!("http://127.0.0.1:{}/chat/edit", address.port()))
+        .header("Authorization", SESSION.to_string())
+        .json(&map)
+        .send()
+        .await
+        .expect("Failed to send request.");
+
+    assert!(response.status().is_success());
+    println!("{:?}", response.text().await);
+
+    // Shutdown the server
+    cancel_token.cancel();
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn edit_message_too_long() {
+    let (server_task, address, cancel_token) = spawn_server().await;
+    let client = reqwest::Client::new();
+
+    let map = MessageEditData {
+        id: 1,
+        msg: "a\n".repeat(100),
+    };
+
+    let response = client
+        .post(format!("http://127.0.0.1:{}/chat/edit", address.port()))
+        .header("Authorization", SESSION.to_string())
+        .json(&map)
+        .send()
+        .await
+        .expect("Failed to send request.");
+
+    assert_eq!(response.status().as_u16(), 409);
+    println!("{:?}", response.text().await);
+
+    // Shutdown the server
+    cancel_token.cancel();
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn delete_message() {
+    let (server_task, address, cancel_token) = spawn_server().await;
+    let client = reqwest::Client::new();
+
+    // Delete a message of our own, the shared fixture messages are used by other tests
+    let settings = get_configuration().expect("Failed to read configuration.");
+    let database = Database::new(&settings.database);
+    let user_id = database
+        .check_session(SESSION.to_string())
+        .await
+        .expect("Invalid test session.");
+    let (message_id, _) = database
+        .insert_message(1, user_id, "to be deleted".to_string(), None, None, None)
+        .await
+        .unwrap();
+
+    let map = MessageDeleteData { id: message_id };
+
+    let response = client
+        .post(format!("http://127.0.0.1:{}/chat/delete", address.port()))
+        .header("Authorization", SESSION.to_string())
+        .json(&map)
+        .send()
+        .await
+        .expect("Failed to send request.");
+
+    assert!(response.status().is_success());
+    println!("{:?}", response.text().await);
+
+    // Shutdown the server
+    cancel_token.cancel();
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn add_and_remove_reaction() {
+    let (server_task, address, cancel_token) = spawn_server().await;
+    let client = reqwest::Client::new();
+
+    let map = ReactionData {
+        id: 1,
+        emoji: ":thumbsup:".to_string(),
+    };
+
+    for action in ["add", "remove"] {
+        let response = client
+            .post(format!(
+                "http://127.0.0.1:{}/chat/reaction/{}",
+                address.port(),
+                action
+            ))
+            .header("Authorization", SESSION.to_string())
+            .json(&map)
+            .send()
+            .await
+            .expect("Failed to send request.");
+
+        assert!(response.status().is_success());
+        println!("{:?}", response.text().await);
+    }
+
+    // Shutdown the server
+    cancel_token.cancel();
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn pin_and_unpin_message() {
+    let (server_task, address, cancel_token) = spawn_server().await;
+    let client = reqwest::Client::new();
+
+    let map = PinData { id: 1 };
+
+    for action in ["pin", "unpin"] {
+        let response = client
+            .post(format!(
+                "http://127.0.0.1:{}/chat/{}",
+                address.port(),
+                action
+            ))
+            .header("Authorization", SESSION.to_string())
+            .json(&map)
+            .send()
+            .await
+            .expect("Failed to send request.");
+
+        assert!(response.status().is_success());
+        println!("{:?}", response.text().await);
+    }
+
+    // Shutdown the server
+    cancel_token.cancel();
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn list_pins() {
+    let (server_task, address, cancel_token) = spawn_server().await;
+    let client = reqwest::Client::new();
+
+    let response = client
+        .get(format!(
+            "http://127.0.0.1:{}/chat/pins?channel=1",
+            address.port()
+        ))
+        .header("Authorization", SESSION.to_string())
+        .send()
+        .await
+        .expect("Failed to send request.");
+
+    assert!(response.status().is_success());
+    println!("{:?}", response.text().await);
+
+    // Shutdown the server
+    cancel_token.cancel();
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn schedule_and_cancel_message() {
+    let (server_task, address, cancel_token) = spawn_server().await;
+    let client = reqwest::Client::new();
+
+    let send_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        + 3600;
+    let map = ScheduleData {
+        channel: 1,
+        msg: "standup in 5 minutes".to_string(),
+        send_at,
+        attachment_id: None,
+        ttl_second: Some(600),
+    };
+
+    let response = client
+        .post(format!("http://127.0.0.1:{}/chat/schedule", address.port()))
+        .header("Authorization", SESSION.to_string())
+        .json(&map)
+        .send()
+        .await
+        .expect("Failed to send request.");
+
+    assert!(response.status().is_success());
+    let body: serde_json::Value = response.json().await.unwrap();
+    let id = body["id"].as_u64().unwrap();
+
+    let response = client
+        .get(format!(
+            "http://127.0.0.1:{}/chat/schedule/list",
+            address.port()
+        ))
+        .header("Authorization", SESSION.to_string())
+        .send()
+        .await
+        .expect("Failed to send request.");
+
+    assert!(response.status().is_success());
+    println!("{:?}", response.text().await);
+
+    let map = ScheduleCancelData { id };
+
+    let response = client
+        .post(format!(
+            "http://127.0.0.1:{}/chat/schedule/cancel",
+            address.port()
+        ))
+        .header("Authorization", SESSION.to_string())
+        .json(&map)
+        .send()
+        .await
+        .expect("Failed to send request.");
+
+    assert!(response.status().is_success());
+
+    // Shutdown the server
+    cancel_token.cancel();
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn schedule_message_in_past() {
+    let (server_task, address, cancel_token) = spawn_server().await;
+    let client = reqwest::Client::new();
+
+    let map = ScheduleData {
+        channel: 1,
+        msg: "too late".to_string(),
+        send_at: 1,
+        attachment_id: None,
+        ttl_second: None,
+    };
+
+    let response = client
+        .post(format!("http://127.0.0.1:{}/chat/schedule", address.port()))
+        .header("Authorization", SESSION.to_string())
+        .json(&map)
+        .send()
+        .await
+        .expect("Failed to send request.");
+
+    assert_eq!(response.status().as_u16(), 409);
+    println!("{:?}", response.text().await);
+
+    // Shutdown the server
+    cancel_token.cancel();
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn open_dm() {
+    let (server_task, address, cancel_token) = spawn_server().await;
+    let client = reqwest::Client::new();
+
+    let map = DmOpenData {
+        usernames: vec!["create_my_id".to_string()],
+    };
+
+    let response = client
+        .post(format!("http://127.0.0.1:{}/chat/dm/open", address.port()))
+        .header("Authorization", SESSION.to_string())
+        .json(&map)
+        .send()
+        .await
+        .expect("Failed to send request.");
+
+    assert!(response.status().is_success());
+    println!("{:?}", response.text().await);
+
+    // Shutdown the server
+    cancel_token.cancel();
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn list_dm() {
+    let (server_task, address, cancel_token) = spawn_server().await;
+    let client = reqwest::Client::new();
+
+    let response = client
+        .get(format!("http://127.0.0.1:{}/chat/dm/list", address.port()))
+        .header("Authorization", SESSION.to_string())
+        .send()
+        .await
+        .expect("Failed to send request.");
+
+    assert!(response.status().is_success());
+    println!("{:?}", response.text().await);
+
+    // Shutdown the server
+    cancel_token.cancel();
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn list_mention() {
+    let (server_task, address, cancel_token) = spawn_server().await;
+    let client = reqwest::Client::new();
+
+    let response = client
+        .get(format!(
+            "http://127.0.0.1:{}/chat/mention/list",
+            address.port()
+        ))
+        .header("Authorization", SESSION.to_string())
+        .send()
+        .await
+        .expect("Failed to send request.");
+
+    assert!(response.status().is_success());
+    println!("{:?}", response.text().await);
+
+    // Shutdown the server
+    cancel_token.cancel();
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn read_mention() {
+    let (server_task, address, cancel_token) = spawn_server().await;
+    let client = reqwest::Client::new();
+
+    let map = MentionReadData { ids: vec![1] };
+
+    let response = client
+        .post(format!(
+            "http://127.0.0.1:{}/chat/mention/read",
+            address.port()
+        ))
+        .header("Authorization", SESSION.to_string())
+        .json(&map)
+        .send()
+        .await
+        .expect("Failed to send request.");
+
+    assert!(response.status().is_success());
+    println!("{:?}", response.text().await);
+
+    // Shutdown the server
+    cancel_token.cancel();
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn mark_read() {
+    let (server_task, address, cancel_token) = spawn_server().await;
+    let client = reqwest::Client::new();
+
+    let map = ReadMarkerData { id: 1 };
+
+    let response = client
+        .post(format!("http://127.0.0.1:{}/chat/read", address.port()))
+        .header("Authorization", SESSION.to_string())
+        .json(&map)
+        .send()
+        .await
+        .expect("Failed to send request.");
+
+    assert!(response.status().is_success());
+    println!("{:?}", response.text().await);
+
+    // Shutdown the server
+    cancel_token.cancel();
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn unread_counts() {
+    let (server_task, address, cancel_token) = spawn_server().await;
+    let client = reqwest::Client::new();
+
+    let response = client
+        .get(format!("http://127.0.0.1:{}/chat/unread", address.port()))
+        .header("Authorization", SESSION.to_string())
+        .send()
+        .await
+        .expect("Failed to send request.");
+
+    assert!(response.status().is_success());
+    println!("{:?}", response.text().await);
+
+    // Shutdown the server
+    cancel_token.cancel();
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn set_status() {
+    let (server_task, address, cancel_token) = spawn_server().await;
+    let client = reqwest::Client::new();
+
+    let map = StatusData {
+        status: Some("do_not_disturb".to_string()),
+        text: Some("in a meeting".to_string()),
+    };
+
+    let response = client
+        .post(format!("http://127.0.0.1:{}/chat/status", address.port()))
+        .header("Authorization", SESSION.to_string())
+        .json(&map)
+        .send()
+        .await
+        .expect("Failed to send request.");
+
+    assert!(response.status().is_success());
+    println!("{:?}", response.text().await);
+
+    // Shutdown the server
+    cancel_token.cancel();
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn search_messages() {
+    let (server_task, address, cancel_token) = spawn_server().await;
+    let client = reqwest::Client::new();
+
+    let response = client
+        .get(format!(
+            "http://127.0.0.1:{}/chat/search?query=hello&server=1&limit=10",
+            address.port()
+        ))
+        .header("Authorization", SESSION.to_string())
+        .send()
+        .await
+        .expect("Failed to send request.");
+
+    assert!(response.status().is_success());
+    println!("{:?}", response.text().await);
+
+    // Shutdown the server
+    cancel_token.cancel();
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn search_mention_syntax() {
+    let (server_task, address, cancel_token) = spawn_server().await;
+    let client = reqwest::Client::new();
+
+    // `@` and unbalanced operators are special in boolean mode queries
+    for query in ["%40alice", "bob%40example.com", "%2Bhello%20(world", "%22"] {
+        let response = client
+            .get(format!(
+                "http://127.0.0.1:{}/chat/search?query={}",
+                address.port(),
+                query
+            ))
+            .header("Authorization", SESSION.to_string())
+            .send()
+            .await
+            .expect("Failed to send request.");
+
+        assert!(response.status().is_success());
+    }
+
+    // Shutdown the server
+    cancel_token.cancel();
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn search_without_query() {
+    let (server_task, address, cancel_token) = spawn_server().await;
+    let client = reqwest::Client::new();
+
+    let response = client
+        .get(format!("http://127.0.0.1:{}/chat/search", address.port()))
+        .header("Authorization", SESSION.to_string())
+        .send()
+        .await
+        .expect("Failed to send request.");
+
+    assert_eq!(response.status().as_u16(), 400);
+    println!("{:?}", response.text().await);
+
+    // Shutdown the server
+    cancel_token.cancel();
+    server_task.await.unwrap();
+}
@@ -0,0 +1,396 @@
+use std::sync::{atomic::AtomicU64, Arc};
+use std::time::Duration;
+use tokio::time::Instant;
+use tui_chat_server::configuration::QueueFullPolicy;
+use tui_chat_server::models::chat::{
+    ClientMessage, Connection, MessageKind, OutboundQueue, PresenceState, Registry, TokenBucket,
+    SLOW_CONSUMER_CLOSE_CODE,
+};
+use tui_chat_server::utils::parse_mentions;
+use warp::ws::Message;
+
+fn queue(depth: usize, policy: QueueFullPolicy) -> Arc<OutboundQueue> {
+    Arc::new(OutboundQueue::new(
+        depth,
+        policy,
+        Arc::new(AtomicU64::new(0)),
+    ))
+}
+
+#[test]
+fn parse_send_frame() {
+    let frame = r#"{"type": "Send", "msg": "hello"}"#;
+    let client_msg = serde_json::from_str::<ClientMessage>(frame).unwrap();
+
+    assert!(matches!(
+        client_msg,
+        ClientMessage::Send { msg, parent_id: None, .. } if msg == "hello"
+    ));
+}
+
+#[test]
+fn parse_reply_frame() {
+    let frame = r#"{"type": "Send", "msg": "+1", "parent_id": 42}"#;
+    let client_msg = serde_json::from_str::<ClientMessage>(frame).unwrap();
+
+    assert!(matches!(
+        client_msg,
+        ClientMessage::Send {
+            parent_id: Some(42),
+            ..
+        }
+    ));
+}
+
+#[test]
+fn parse_switch_channel_frame() {
+    let frame = r#"{"type": "SwitchChannel", "channel": 3}"#;
+    let client_msg = serde_json::from_str::<ClientMessage>(frame).unwrap();
+
+    assert!(matches!(
+        client_msg,
+        ClientMessage::SwitchChannel { channel: 3 }
+    ));
+}
+
+#[test]
+fn parse_mark_read_frame() {
+    let frame = r#"{"type": "MarkRead", "message_id": 42}"#;
+    let client_msg = serde_json::from_str::<ClientMessage>(frame).unwrap();
+
+    assert!(matches!(
+        client_msg,
+        ClientMessage::MarkRead { message_id: 42 }
+    ));
+}
+
+#[test]
+fn reject_malformed_frames() {
+    for frame in [
+        "hello",
+        r#"{"msg": "missing type"}"#,
+        r#"{"type": "Unknown"}"#,
+        r#"{"type": "Send"}"#,
+    ] {
+        assert!(serde_json::from_str::<ClientMessage>(frame).is_err());
+    }
+}
+
+#[test]
+fn serialize_error_event() {
+    let error_msg = MessageKind::Error {
+        code: "malformed_frame".to_string(),
+        reason: "expected value".to_string(),
+    };
+
+    assert_eq!(
+        serde_json::to_string(&error_msg).unwrap(),
+        r#"{"type":"Error","code":"malformed_frame","reason":"expected value"}"#
+    );
+}
+
+#[test]
+fn parse_subscribe_frame() {
+    let frame = r#"{"type": "Subscribe", "channel": 7}"#;
+    let client_msg = serde_json::from_str::<ClientMessage>(frame).unwrap();
+
+    assert!(matches!(
+        client_msg,
+        ClientMessage::Subscribe { channel: 7, .. }
+    ));
+}
+
+#[test]
+fn deliver_only_subscribed_channels() {
+    let queue = queue(8, QueueFullPolicy::DropOldest);
+    let mut connection = Connection::new(1, "user".to_string(), queue.clone());
+    connection.channels.insert(2);
+    connection.channels.insert(5);
+
+    let join_msg = MessageKind::Join {
+        id: 3,
+        username: "other".to_string(),
+    };
+    assert!(!connection.send(4, &join_msg));
+    assert!(connection.send(5, &join_msg));
+
+    let message = queue.try_pop().unwrap();
+    assert_eq!(
+        message.to_str().unwrap(),
+        r#"{"channel":5,"type":"Join","id":3,"username":"other"}"#
+    );
+    assert!(queue.try_pop().is_none());
+}
+
+#[test]
+fn registry_indexes_channels_and_users() {
+    let mut registry = Registry::default();
+    let own_queue = queue(8, QueueFullPolicy::DropOldest);
+    let other_queue = queue(8, QueueFullPolicy::DropOldest);
+    registry.insert(
+        "a".to_string(),
+        Connection::new(1, "one".to_string(), own_queue.clone()),
+    );
+    registry.insert(
+        "b".to_string(),
+        Connection::new(1, "one".to_string(), own_queue.clone()),
+    );
+    registry.insert(
+        "c".to_string(),
+        Connection::new(2, "two".to_string(), other_queue.clone()),
+    );
+
+    assert!(registry.subscribe("a", 10));
+    assert!(!registry.subscribe("a", 10));
+    assert!(registry.subscribe("c", 10));
+    assert!(registry.subscribe("c", 11));
+    assert_eq!(registry.channel_connections(10).count(), 2);
+    assert_eq!(registry.user_connections(1).count(), 2);
+    assert!(registry.is_user_in_channel(1, 10, "b"));
+    assert!(!registry.is_user_in_channel(1, 10, "a"));
+
+    registry.broadcast(11, &MessageKind::Pong, "");
+    assert!(own_queue.try_pop().is_none());
+    assert!(other_queue.try_pop().is_some());
+
+    registry.remove("c", &other_queue);
+    assert_eq!(registry.channel_connections(10).count(), 1);
+    assert_eq!(registry.channel_connections(11).count(), 0);
+    assert!(registry.unsubscribe("a", 10));
+    assert_eq!(registry.channel_connections(10).count(), 0);
+    assert_eq!(registry.len(), 2);
+}
+
+#[test]
+fn replaced_connection_cannot_remove_its_successor() {
+    let mut registry = Registry::default();
+    let stale_queue = queue(8, QueueFullPolicy::DropOldest);
+    let resumed_queue = queue(8, QueueFullPolicy::DropOldest);
+    assert!(registry
+        .insert(
+            "a".to_string(),
+            Connection::new(1, "one".to_string(), stale_queue.clone()),
+        )
+        .is_none());
+    assert!(registry.subscribe("a", 10));
+
+    let replaced = registry.insert(
+        "a".to_string(),
+        Connection::new(1, "one".to_string(), resumed_queue.clone()),
+    );
+    assert!(replaced.is_some_and(|connection| connection.channels.contains(&10)));
+    assert_eq!(registry.channel_connections(10).count(), 0);
+
+    // The stale session cleaning up leaves the resumed one alone
+    assert!(registry.remove("a", &stale_queue).is_none());
+    assert_eq!(registry.len(), 1);
+    assert!(registry.remove("a", &resumed_queue).is_some());
+    assert!(registry.is_empty());
+}
+
+#[tokio::test]
+async fn sequencer_is_shared_per_channel() {
+    let registry = Registry::default();
+    let sequencer = registry.sequencer(10);
+    let _sequenced = sequencer.lock().await;
+
+    // Publishers in the same channel wait, other channels go ahead
+    assert!(registry.sequencer(10).try_lock().is_err());
+    assert!(registry.sequencer(11).try_lock().is_ok());
+}
+
+#[test]
+fn full_queue_drops_oldest() {
+    let dropped_total = Arc::new(AtomicU64::new(0));
+    let queue = OutboundQueue::new(2, QueueFullPolicy::DropOldest, dropped_total.clone());
+
+    for text in ["1", "2", "3"] {
+        assert!(queue.push(Message::text(text)));
+    }
+
+    assert_eq!(queue.dropped(), 1);
+    assert_eq!(dropped_total.load(std::sync::atomic::Ordering::Relaxed), 1);
+    assert_eq!(queue.try_pop().unwrap().to_str().unwrap(), "2");
+    assert_eq!(queue.try_pop().unwrap().to_str().unwrap(), "3");
+    assert!(queue.try_pop().is_none());
+}
+
+#[tokio::test]
+async fn full_queue_disconnects() {
+    let queue = queue(2, QueueFullPolicy::Disconnect);
+
+    assert!(queue.push(Message::text("1")));
+    assert!(queue.push(Message::text("2")));
+    assert!(!queue.push(Message::text("3")));
+    assert!(!queue.push(Message::text("4")));
+
+    // Pending messages are replaced by a close frame, then the queue ends
+    queue.closed().await;
+    let close = queue.pop().await.unwrap();
+    assert_eq!(close.close_frame().unwrap().0, SLOW_CONSUMER_CLOSE_CODE);
+    assert!(queue.pop().await.is_none());
+    assert_eq!(queue.dropped(), 1);
+}
+
+#[test]
+fn parse_resume_subscribe_frame() {
+    let frame = r#"{"type": "Subscribe", "channel": 7, "last_seq": 12}"#;
+    let client_msg = serde_json::from_str::<ClientMessage>(frame).unwrap();
+
+    assert!(matches!(
+        client_msg,
+        ClientMessage::Subscribe {
+            channel: 7,
+            last_seq: Some(12)
+        }
+    ));
+}
+
+#[test]
+fn deliver_sequenced_events() {
+    let queue = queue(8, QueueFullPolicy::DropOldest);
+    let mut connection = Connection::new(1, "user".to_string(), queue.clone());
+    connection.channels.insert(5);
+
+    let deleted_msg = MessageKind::Deleted { message_id: 9 };
+    assert!(connection.send_sequenced(5, 13, &deleted_msg));
+
+    let message = queue.try_pop().unwrap();
+    assert_eq!(
+        message.to_str().unwrap(),
+        r#"{"channel":5,"seq":13,"type":"Deleted","message_id":9}"#
+    );
+}
+
+#[test]
+fn parse_send_frame_with_nonce() {
+    let frame = r#"{"type": "Send", "msg": "hi", "nonce": "c1-42"}"#;
+    let client_msg = serde_json::from_str::<ClientMessage>(frame).unwrap();
+
+    assert!(matches!(
+        client_msg,
+        ClientMessage::Send { nonce: Some(nonce), .. } if nonce == "c1-42"
+    ));
+}
+
+#[test]
+fn parse_send_frame_with_ttl() {
+    let frame = r#"{"type": "Send", "msg": "gone soon", "ttl_second": 60}"#;
+    let client_msg = serde_json::from_str::<ClientMessage>(frame).unwrap();
+
+    assert!(matches!(
+        client_msg,
+        ClientMessage::Send {
+            ttl_second: Some(60),
+            ..
+        }
+    ));
+}
+
+#[test]
+fn serialize_ack_event() {
+    let ack_msg = MessageKind::Ack {
+        nonce: "c1-42".to_string(),
+        message_id: 7,
+        timestamp: 1700000000,
+    };
+
+    assert_eq!(
+        serde_json::to_string(&ack_msg).unwrap(),
+        r#"{"type":"Ack","nonce":"c1-42","message_id":7,"timestamp":1700000000}"#
+    );
+}
+
+#[test]
+fn serialize_pin_event() {
+    let pin_msg = MessageKind::Pin {
+        message_id: 7,
+        id: 3,
+        pinned: true,
+    };
+
+    assert_eq!(
+        serde_json::to_string(&pin_msg).unwrap(),
+        r#"{"type":"Pin","message_id":7,"id":3,"pinned":true}"#
+    );
+}
+
+#[test]
+fn token_bucket_limits_bursts() {
+    let start = Instant::now();
+    let mut bucket = TokenBucket::new(2, start);
+
+    assert!(bucket.try_take(2, 1.0, start).is_ok());
+    assert!(bucket.try_take(2, 1.0, start).is_ok());
+    assert_eq!(bucket.try_take(2, 1.0, start), Err(Duration::from_secs(1)));
+
+    // Refills over time but never beyond the burst size
+    let later = start + Duration::from_secs(10);
+    assert!(bucket.try_take(2, 1.0, later).is_ok());
+    assert!(bucket.try_take(2, 1.0, later).is_ok());
+    assert!(bucket.try_take(2, 1.0, later).is_err());
+}
+
+#[test]
+fn rate_limit_is_shared_per_user() {
+    let mut registry = Registry::default();
+    let now = Instant::now();
+    let own_queue = queue(8, QueueFullPolicy::DropOldest);
+    for token in ["a", "b"] {
+        registry.insert(
+            token.to_string(),
+            Connection::new(1, "one".to_string(), own_queue.clone()),
+        );
+    }
+
+    assert!(registry.take_send_token(1, 1, 0.5, now).is_ok());
+    assert!(registry.take_send_token(1, 1, 0.5, now).is_err());
+    assert!(registry.take_send_token(2, 1, 0.5, now).is_ok());
+
+    // Reconnecting doesn't refill the bucket
+    registry.remove("a", &own_queue);
+    registry.remove("b", &own_queue);
+    assert!(registry.take_send_token(1, 1, 0.5, now).is_err());
+
+    // Refilled buckets are forgotten without changing the outcome
+    let later = now + Duration::from_secs(120);
+    assert!(registry.take_send_token(1, 1, 0.5, later).is_ok());
+    assert!(registry.take_send_token(1, 1, 0.5, later).is_err());
+}
+
+#[test]
+fn parse_mentions_in_content() {
+    assert_eq!(
+        parse_mentions("hi @alice, @bob! and @alice again (@carol) a@b @ @"),
+        vec!["alice", "bob", "carol"]
+    );
+    assert!(parse_mentions("no mentions here").is_empty());
+}
+
+#[test]
+fn presence_follows_connections_and_activity() {
+    let mut registry = Registry::default();
+    let start = Instant::now();
+    let idle_after = Duration::from_secs(60);
+    assert_eq!(registry.presence(1), PresenceState::Offline);
+
+    let own_queue = queue(8, QueueFullPolicy::DropOldest);
+    registry.insert(
+        "a".to_string(),
+        Connection::new(1, "one".to_string(), own_queue.clone()),
+    );
+    assert!(!registry.touch(1, start));
+    assert_eq!(registry.presence(1), PresenceState::Online);
+
+    // Only inactivity for the whole idle time makes the user idle, once
+    assert!(!registry.mark_idle(1, start + Duration::from_secs(30), idle_after));
+    assert!(registry.mark_idle(1, start + idle_after, idle_after));
+    assert!(!registry.mark_idle(1, start + idle_after, idle_after));
+    assert_eq!(registry.presence(1), PresenceState::Idle);
+
+    assert!(registry.touch(1, start + idle_after));
+    assert_eq!(registry.presence(1), PresenceState::Online);
+
+    registry.remove("a", &own_queue);
+    assert_eq!(registry.presence(1), PresenceState::Offline);
+}
@@ -70,6 +70,31 @@ async fn test_signup() {
     server_task.await.unwrap();
 }
 
+#[tokio::test]
+async fn test_signup_with_escape_sequence() {
+    let (server_task, address, cancel_token) = spawn_server().await;
+    let client = reqwest::Client::new();
+
+    let map = SignupData {
+        username: "\u{1b}[2Jadmin".to_string(),
+        pw: "creat_my_pw".to_string(),
+    };
+
+    let response = client
+        .post(format!("http://127.0.0.1:{}/auth/signup", address.port()))
+        .json(&map)
+        .send()
+        .await
+        .expect("Failed to send request.");
+
+    assert_eq!(response.status().as_u16(), 409);
+    println!("{:?}", response.text().await);
+
+    // Shutdown the server
+    cancel_token.cancel();
+    server_task.await.unwrap();
+}
+
 #[tokio::test]
 async fn test_logout() {
     let (server_task, address, cancel_token) = spawn_server().await;
@@ -0,0 +1,122 @@
+use proptest::prelude::*;
+use tui_chat_server::configuration::SanitizePolicy;
+use tui_chat_server::sanitize::sanitize;
+
+const POLICIES: [SanitizePolicy; 3] = [
+    SanitizePolicy::Strip,
+    SanitizePolicy::Escape,
+    SanitizePolicy::AllowSgr,
+];
+
+/// Fragments that terminals treat specially, mixed into generated content.
+fn terminal_fragment() -> impl Strategy<Value = String> {
+    prop_oneof![
+        Just("\u{1b}[2J".to_string()),
+        Just("\u{1b}]0;owned\u{07}".to_string()),
+        Just("\u{1b}]8;;https://example.com\u{1b}\\link\u{1b}]8;;\u{1b}\\".to_string()),
+        Just("\u{1b}P+q\u{1b}\\".to_string()),
+        Just("\u{1b}[31;1m".to_string()),
+        Just("\u{1b}[0m".to_string()),
+        Just("\u{9b}2J".to_string()),
+        Just("\u{1b}".to_string()),
+        Just("\u{1b}[".to_string()),
+        Just("\r".to_string()),
+        "[\\x00-\\x1f\\x7f-\\x9f]",
+        "[a-z ]{0,8}",
+        any::<char>().prop_map(String::from),
+    ]
+}
+
+fn content() -> impl Strategy<Value = String> {
+    prop::collection::vec(terminal_fragment(), 0..16).prop_map(|fragments| fragments.concat())
+}
+
+fn has_forbidden_control(text: &str) -> bool {
+    text.chars()
+        .any(|c| c.is_control() && c != '\n' && c != '\t')
+}
+
+/// Checks that every escape is a complete SGR sequence.
+fn only_sgr(text: &str) -> bool {
+    let mut rest = text;
+    while let Some(start) = rest.find('\u{1b}') {
+        rest = &rest[start + 1..];
+        let Some(params) = rest.strip_prefix('[') else {
+            return false;
+        };
+        let Some(end) = params.find('m') else {
+            return false;
+        };
+        if !params[..end]
+            .chars()
+            .all(|c| c.is_ascii_digit() || c == ';')
+        {
+            return false;
+        }
+        rest = &params[end + 1..];
+    }
+    !text
+        .chars()
+        .any(|c| c.is_control() && !matches!(c, '\n' | '\t' | '\u{1b}'))
+}
+
+#[test]
+fn strip_removes_sequences() {
+    assert_eq!(
+        sanitize("a\u{1b}[2Jb\u{1b}]0;title\u{07}c\rd", SanitizePolicy::Strip),
+        "abcd"
+    );
+    assert_eq!(
+        sanitize("\u{1b}[31mred\u{1b}[0m", SanitizePolicy::Strip),
+        "red"
+    );
+    assert_eq!(
+        sanitize("line\n\tindent", SanitizePolicy::Strip),
+        "line\n\tindent"
+    );
+}
+
+#[test]
+fn escape_shows_sequences() {
+    assert_eq!(
+        sanitize("a\u{1b}[2Jb\u{07}\u{7f}\u{9b}", SanitizePolicy::Escape),
+        "a^[[2Jb^G^?M-^["
+    );
+}
+
+#[test]
+fn allow_sgr_keeps_colors_only() {
+    assert_eq!(
+        sanitize("\u{1b}[1;31mhi\u{1b}[0m", SanitizePolicy::AllowSgr),
+        "\u{1b}[1;31mhi\u{1b}[0m"
+    );
+    // Unterminated colors are reset, other sequences are removed
+    assert_eq!(
+        sanitize("\u{1b}[32mok\u{1b}[2J\u{1b}[?25l", SanitizePolicy::AllowSgr),
+        "\u{1b}[32mok\u{1b}[0m"
+    );
+}
+
+proptest! {
+    #[test]
+    fn output_has_no_forbidden_controls(text in content()) {
+        prop_assert!(!has_forbidden_control(&sanitize(&text, SanitizePolicy::Strip)));
+        prop_assert!(!has_forbidden_control(&sanitize(&text, SanitizePolicy::Escape)));
+        prop_assert!(only_sgr(&sanitize(&text, SanitizePolicy::AllowSgr)));
+    }
+
+    #[test]
+    fn sanitize_is_idempotent(text in content()) {
+        for policy in POLICIES {
+            let once = sanitize(&text, policy);
+            prop_assert_eq!(sanitize(&once, policy), once);
+        }
+    }
+
+    #[test]
+    fn plain_text_is_unchanged(text in "[^\\p{Cc}]*") {
+        for policy in POLICIES {
+            prop_assert_eq!(sanitize(&text, policy), text.clone());
+        }
+    }
+}
@@ -36,6 +36,34 @@ pub struct ServerDeleteData {
     pub id: u64,
 }
 
+#[derive(Clone, Serialize)]
+pub struct ChannelCreateData {
+    pub server_id: u64,
+    pub name: String,
+}
+
+#[derive(Clone, Serialize)]
+pub struct ChannelListData {
+    pub server_id: u64,
+}
+
+#[derive(Clone, Serialize)]
+pub struct ChannelRenameData {
+    pub id: u64,
+    pub name: String,
+}
+
+#[derive(Clone, Serialize)]
+pub struct ChannelDeleteData {
+    pub id: u64,
+}
+
+#[derive(Clone, Serialize)]
+pub struct ChannelSlowModeData {
+    pub id: u64,
+    pub second: u32,
+}
+
 #[tokio::test]
 async fn create_server() {
     let (server_task, address, cancel_token) = spawn_server().await;
@@ -66,6 +94,35 @@ async fn create_server() {
     server_task.await.unwrap();
 }
 
+#[tokio::test]
+async fn create_server_with_escape_sequence() {
+    let (server_task, address, cancel_token) = spawn_server().await;
+    let client = reqwest::Client::new();
+
+    let map = ServerCreateData {
+        name: "\u{1b}[2Jtest".to_string(),
+        public: true,
+    };
+
+    let response = client
+        .post(format!(
+            "http://127.0.0.1:{}/server/manage/create",
+            address.port()
+        ))
+        .header("Authorization", SESSION.to_string())
+        .json(&map)
+        .send()
+        .await
+        .expect("Failed to send request.");
+
+    assert_eq!(response.status().as_u16(), 409);
+    println!("{:?}", response.text().await);
+
+    // Shutdown the server
+    cancel_token.cancel();
+    server_task.await.unwrap();
+}
+
 #[tokio::test]
 async fn search_server() {
     let (server_task, address, cancel_token) = spawn_server().await;
@@ -117,6 +174,32 @@ async fn get_invite_code() {
     server_task.await.unwrap();
 }
 
+#[tokio::test]
+async fn server_presence() {
+    let (server_task, address, cancel_token) = spawn_server().await;
+    let client = reqwest::Client::new();
+
+    let map = GetInviteCodeData { id: 1 };
+
+    let response = client
+        .post(format!(
+            "http://127.0.0.1:{}/server/presence",
+            address.port()
+        ))
+        .header("Authorization", SESSION.to_string())
+        .json(&map)
+        .send()
+        .await
+        .expect("Failed to send request.");
+
+    assert!(response.status().is_success());
+    println!("{:?}", response.text().await);
+
+    // Shutdown the server
+    cancel_token.cancel();
+    server_task.await.unwrap();
+}
+
 #[tokio::test]
 async fn join_server() {
     let (server_task, address, cancel_token) = spawn_server().await;
@@ -197,3 +280,168 @@ async fn delete_server() {
     cancel_token.cancel();
     server_task.await.unwrap();
 }
+
+#[tokio::test]
+async fn create_channel() {
+    let (server_task, address, cancel_token) = spawn_server().await;
+    let client = reqwest::Client::new();
+
+    let map = ChannelCreateData {
+        server_id: 1,
+        name: "general".to_string(),
+    };
+
+    let response = client
+        .post(format!(
+            "http://127.0.0.1:{}/server/manage/channel/create",
+            address.port()
+        ))
+        .header("Authorization", SESSION.to_string())
+        .json(&map)
+        .send()
+        .await
+        .expect("Failed to send request.");
+
+    assert!(response.status().is_success());
+    println!("{:?}", response.text().await);
+
+    // Shutdown the server
+    cancel_token.cancel();
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn create_channel_with_escape_sequence() {
+    let (server_task, address, cancel_token) = spawn_server().await;
+    let client = reqwest::Client::new();
+
+    let map = ChannelCreateData {
+        server_id: 1,
+        name: "\u{1b}[2Jgeneral".to_string(),
+    };
+
+    let response = client
+        .post(format!(
+            "http://127.0.0.1:{}/server/manage/channel/create",
+            address.port()
+        ))
+        .header("Authorization", SESSION.to_string())
+        .json(&map)
+        .send()
+        .await
+        .expect("Failed to send request.");
+
+    assert_eq!(response.status().as_u16(), 409);
+    println!("{:?}", response.text().await);
+
+    // Shutdown the server
+    cancel_token.cancel();
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn list_channel() {
+    let (server_task, address, cancel_token) = spawn_server().await;
+    let client = reqwest::Client::new();
+
+    let map = ChannelListData { server_id: 1 };
+
+    let response = client
+        .post(format!(
+            "http://127.0.0.1:{}/server/manage/channel/list",
+            address.port()
+        ))
+        .header("Authorization", SESSION.to_string())
+        .json(&map)
+        .send()
+        .await
+        .expect("Failed to send request.");
+
+    assert!(response.status().is_success());
+    println!("{:?}", response.text().await);
+
+    // Shutdown the server
+    cancel_token.cancel();
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn rename_channel() {
+    let (server_task, address, cancel_token) = spawn_server().await;
+    let client = reqwest::Client::new();
+
+    let map = ChannelRenameData {
+        id: 1,
+        name: "random".to_string(),
+    };
+
+    let response = client
+        .post(format!(
+            "http://127.0.0.1:{}/server/manage/channel/rename",
+            address.port()
+        ))
+        .header("Authorization", SESSION.to_string())
+        .json(&map)
+        .send()
+        .await
+        .expect("Failed to send request.");
+
+    assert!(response.status().is_success());
+    println!("{:?}", response.text().await);
+
+    // Shutdown the server
+    cancel_token.cancel();
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn slow_mode_channel() {
+    let (server_task, address, cancel_token) = spawn_server().await;
+    let client = reqwest::Client::new();
+
+    let map = ChannelSlowModeData { id: 1, second: 10 };
+
+    let response = client
+        .post(format!(
+            "http://127.0.0.1:{}/server/manage/channel/slow_mode",
+            address.port()
+        ))
+        .header("Authorization", SESSION.to_string())
+        .json(&map)
+        .send()
+        .await
+        .expect("Failed to send request.");
+
+    assert!(response.status().is_success());
+    println!("{:?}", response.text().await);
+
+    // Shutdown the server
+    cancel_token.cancel();
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn delete_channel() {
+    let (server_task, address, cancel_token) = spawn_server().await;
+    let client = reqwest::Client::new();
+
+    let map = ChannelDeleteData { id: 1 };
+
+    let response = client
+        .post(format!(
+            "http://127.0.0.1:{}/server/manage/channel/delete",
+            address.port()
+        ))
+        .header("Authorization", SESSION.to_string())
+        .json(&map)
+        .send()
+        .await
+        .expect("Failed to send request.");
+
+    assert!(response.status().is_success());
+    println!("{:?}", response.text().await);
+
+    // Shutdown the server
+    cancel_token.cancel();
+    server_task.await.unwrap();
+}
@@ -0,0 +1,72 @@
+use tui_chat_server::configuration::{
+    AllowedCharacters, ChatSettings, QueueFullPolicy, SanitizePolicy,
+};
+use tui_chat_server::validate::{validate_content, ContentError};
+
+fn settings(allowed_characters: AllowedCharacters) -> ChatSettings {
+    ChatSettings {
+        queue_depth: 256,
+        queue_full_policy: QueueFullPolicy::DropOldest,
+        ping_interval_second: 30,
+        pong_timeout_second: 10,
+        dedup_window_second: 300,
+        rate_limit_burst: 5,
+        rate_limit_per_second: 1.0,
+        sanitize_policy: SanitizePolicy::Strip,
+        max_message_bytes: 24,
+        max_message_graphemes: 4,
+        max_message_lines: 2,
+        allowed_characters,
+        idle_after_second: 300,
+        scheduler_interval_second: 1,
+        max_message_ttl_second: 604800,
+    }
+}
+
+#[test]
+fn accept_valid_content() {
+    let settings = settings(AllowedCharacters::Visible);
+
+    assert_eq!(validate_content("hi", &settings), Ok(()));
+    assert_eq!(validate_content("a\nb", &settings), Ok(()));
+    // A family emoji is one grapheme made of several code points
+    assert_eq!(
+        validate_content("\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}", &settings),
+        Ok(())
+    );
+}
+
+#[test]
+fn reject_content_over_limits() {
+    let settings = settings(AllowedCharacters::Any);
+
+    assert_eq!(validate_content(" \n", &settings), Err(ContentError::Empty));
+    assert_eq!(
+        validate_content("hello", &settings),
+        Err(ContentError::TooManyGraphemes(4))
+    );
+    assert_eq!(
+        validate_content("\u{1f46a}".repeat(7).as_str(), &settings),
+        Err(ContentError::TooManyBytes(24))
+    );
+    assert_eq!(
+        validate_content("\n\n\na", &settings),
+        Err(ContentError::TooManyLines(2))
+    );
+}
+
+#[test]
+fn reject_disallowed_characters() {
+    assert_eq!(
+        validate_content("a\u{202e}b", &settings(AllowedCharacters::Visible)),
+        Err(ContentError::DisallowedCharacter('\u{202e}'))
+    );
+    assert_eq!(
+        validate_content("caf\u{e9}", &settings(AllowedCharacters::Ascii)),
+        Err(ContentError::DisallowedCharacter('\u{e9}'))
+    );
+    assert_eq!(
+        validate_content("a\u{202e}b", &settings(AllowedCharacters::Any)),
+        Ok(())
+    );
+}